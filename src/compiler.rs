@@ -0,0 +1,107 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum Compiler {
+    #[serde(rename = "IDO")]
+    Ido,
+    #[serde(rename = "GCC")]
+    Gcc,
+    #[serde(rename = "unknown")]
+    Unknown,
+}
+
+impl std::fmt::Display for Compiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Compiler::Ido => "IDO",
+                Compiler::Gcc => "GCC",
+                Compiler::Unknown => "unknown",
+            }
+        )
+    }
+}
+
+/// Counts of unconditional branches vs jumps in the boot segment: IDO prefers `b`, GCC prefers `j`
+#[derive(Debug, Clone, Copy)]
+pub struct CompilerGuess {
+    /// Number of bytes examined
+    pub examined: usize,
+    /// Image offset after the last `jr $ra` found
+    pub text_end: Option<usize>,
+    pub b_count: u32,
+    pub j_count: u32,
+}
+
+impl CompilerGuess {
+    pub fn compiler(&self) -> Compiler {
+        if self.b_count + self.j_count < 100 {
+            Compiler::Unknown
+        } else if self.b_count > self.j_count {
+            Compiler::Ido
+        } else {
+            Compiler::Gcc
+        }
+    }
+}
+
+impl Serialize for CompilerGuess {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CompilerGuess", 5)?;
+        state.serialize_field("examined", &self.examined)?;
+        state.serialize_field("text_end", &self.text_end)?;
+        state.serialize_field("b_count", &self.b_count)?;
+        state.serialize_field("j_count", &self.j_count)?;
+        state.serialize_field("compiler", &self.compiler())?;
+        state.end()
+    }
+}
+
+/// Count branches and jumps in big-endian code found at offset `start` of the image
+pub fn guess_gcc_or_ido(data: &[u8], start: usize) -> CompilerGuess {
+    let mut j_count = 0;
+    let mut b_count = 0;
+
+    log::debug!("Examining up to {:#X} bytes", data.len());
+
+    let mut in_function = false;
+    let mut text_end = None;
+    // let mut consecutive_nops = 0;
+    for (i, chunk) in data.chunks_exact(4).rev().enumerate() {
+        let word = u32::from_be_bytes(chunk.try_into().unwrap());
+        let instr = rabbitizer::Instruction::new(word, 0);
+
+        // if instr.is_nop() {
+        //     consecutive_nops += 1;
+        // } else {
+        //     consecutive_nops = 0;
+        // }
+        if instr.is_jr_ra() {
+            in_function = true;
+            if text_end.is_none() {
+                text_end = Some(start + data.len() - 4 * i);
+            }
+        } else if !instr.is_valid() {
+            in_function = false
+        }
+
+        if in_function {
+            match instr.instr_id() {
+                rabbitizer::InstrId::RABBITIZER_INSTR_ID_cpu_b => b_count += 1,
+                rabbitizer::InstrId::RABBITIZER_INSTR_ID_cpu_j => j_count += 1,
+                _ => (),
+            }
+        }
+    }
+
+    log::debug!("B count: {b_count}, J count: {j_count}");
+
+    CompilerGuess {
+        examined: data.len(),
+        text_end,
+        b_count,
+        j_count,
+    }
+}
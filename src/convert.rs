@@ -0,0 +1,130 @@
+//! Streaming conversion between the three ROM byte orders, for images too big to hold in memory
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::n64header::detect::{self, Confidence};
+use crate::n64header::{self, Endian};
+use crate::reend_array;
+
+/// Bytes converted at a time, a whole number of words
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Fill as much of `buf` as the reader has left, returning how much was read
+fn read_chunk(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Copy a ROM from `reader` to `writer`, changing its byte order from `from` to `to`.
+/// `progress` is called with the number of bytes written so far after each chunk.
+/// An odd-sized dump is padded with zeros to a whole word when its byte order changes, since
+/// the bytes of its last word are spread across all four positions.
+pub fn convert(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    from: Endian,
+    to: Endian,
+    mut progress: impl FnMut(u64),
+) -> Result<u64> {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut written = 0;
+    loop {
+        let len = read_chunk(&mut reader, &mut buf)?;
+        if len == 0 {
+            break;
+        }
+        let padded = (len + 3) & !3;
+        buf[len..padded].fill(0);
+        reend_array(&mut buf[..padded], &from);
+        reend_array(&mut buf[..padded], &to);
+        let len = if from == to { len } else { padded };
+        writer.write_all(&buf[..len])?;
+        written += len as u64;
+        progress(written);
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+/// Convert the ROM at `input` to byte order `to`, writing it to `output`, which may be the same
+/// file. The input's byte order must be known with at least `needed` confidence. The result is
+/// written beside `output` first and only moved into place once its header magic has been checked.
+pub fn convert_file(
+    input: &Path,
+    output: &Path,
+    to: Endian,
+    needed: Confidence,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<Endian> {
+    let mut reader = File::open(input)?;
+    let total = reader.metadata()?.len();
+    let prefix = detect::read_detect_prefix(&mut reader)?;
+    let from = detect::detect_endian(&prefix)?.require(needed)?;
+
+    let mut temp_name = output.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    let temp = Path::new(&temp_name);
+    let result = (|| {
+        let writer = BufWriter::new(File::create(temp)?);
+        convert(
+            io::Cursor::new(prefix).chain(reader),
+            writer,
+            from,
+            to,
+            |written| progress(written, total),
+        )?;
+
+        let written = detect::read_detect_prefix(&mut File::open(temp)?)?;
+        if n64header::get_endian(&written)? == to {
+            Ok(())
+        } else {
+            Err(Error::UnknownByteOrder(written[..4].try_into().unwrap()))
+        }
+    })();
+
+    match result {
+        Ok(()) => {
+            fs::rename(temp, output)?;
+            Ok(from)
+        }
+        Err(err) => {
+            // Leave the original alone, but do not litter
+            let _ = fs::remove_file(temp);
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert_bytes(input: &[u8], from: Endian, to: Endian) -> Vec<u8> {
+        let mut output = Vec::new();
+        convert(input, &mut output, from, to, |_| ()).unwrap();
+        output
+    }
+
+    #[test]
+    fn odd_sized_dump_keeps_every_byte() {
+        let v64 = [0x37, 0x80, 0x40, 0x12, b'a', b'b', b'c'];
+        let z64 = convert_bytes(&v64, Endian::Ugly, Endian::Good);
+        assert_eq!(z64, [0x80, 0x37, 0x12, 0x40, b'b', b'a', 0, b'c']);
+        assert_eq!(convert_bytes(&z64, Endian::Good, Endian::Ugly)[..7], v64);
+
+        let n64 = convert_bytes(&v64, Endian::Ugly, Endian::Bad);
+        assert_eq!(n64, [0x40, 0x12, 0x37, 0x80, b'c', 0, b'a', b'b']);
+        assert_eq!(convert_bytes(&v64, Endian::Ugly, Endian::Ugly), v64);
+    }
+}
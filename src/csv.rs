@@ -0,0 +1,316 @@
+//! Minimal RFC 4180 CSV output for ROM reports
+
+use std::borrow::Cow;
+use std::io;
+
+use crate::{Error, Result, RomInfo};
+
+/// A named column, and how to get its value from a ROM's file name and analysis
+pub struct Column {
+    pub name: &'static str,
+    value: fn(&str, &RomInfo) -> String,
+}
+
+impl Column {
+    const fn new(name: &'static str, value: fn(&str, &RomInfo) -> String) -> Column {
+        Column { name, value }
+    }
+
+    pub fn value(&self, file_name: &str, info: &RomInfo) -> String {
+        (self.value)(file_name, info)
+    }
+}
+
+/// Every available column, in default output order
+pub const COLUMNS: &[Column] = &[
+    Column::new("file", |name, _| name.to_string()),
+    Column::new("file_size", |_, info| format!("{:X}", info.file_size)),
+    Column::new("real_size", |_, info| format!("{:X}", info.size.real_size)),
+    Column::new("data_end", |_, info| format!("{:X}", info.size.data_end)),
+    Column::new("cart_size", |_, info| format!("{:X}", info.size.cart_size)),
+    Column::new("endian", |_, info| info.endian.extension().to_string()),
+    Column::new("clock_rate", |_, info| {
+        format!("{:08X}", info.header.clock_rate())
+    }),
+    Column::new("entrypoint", |_, info| {
+        format!("{:08X}", info.header.entrypoint())
+    }),
+    Column::new("revision", |_, info| {
+        format!("{:08X}", info.header.revision())
+    }),
+    Column::new("libultra_version", |_, info| {
+        // Revision 0 and other unprintable letters would leave control bytes in the CSV
+        info.header
+            .libultra_version()
+            .filter(char::is_ascii_graphic)
+            .map(String::from)
+            .unwrap_or_default()
+    }),
+    Column::new("libultra_release", |_, info| {
+        info.libultra.release_name().unwrap_or_default()
+    }),
+    Column::new("checksum1", |_, info| {
+        format!("{:08X}", info.header.checksum().0)
+    }),
+    Column::new("checksum2", |_, info| {
+        format!("{:08X}", info.header.checksum().1)
+    }),
+    Column::new("image_name", |_, info| {
+        info.header
+            .image_name()
+            .trim_end_matches(['\0', ' '])
+            .to_string()
+    }),
+    Column::new("media_format", |_, info| {
+        info.header.media_format().to_string()
+    }),
+    Column::new("cartridge_id", |_, info| info.header.cartridge_id()),
+    Column::new("country_code", |_, info| {
+        info.header.country_code().to_string()
+    }),
+    Column::new("version", |_, info| format!("{:X}", info.header.version())),
+    Column::new("save_type", |_, info| {
+        info.header
+            .homebrew()
+            .map(|flags| flags.save_type.to_string())
+            .unwrap_or_default()
+    }),
+    Column::new("rtc", |_, info| {
+        info.header
+            .homebrew()
+            .map(|flags| flags.rtc.to_string())
+            .unwrap_or_default()
+    }),
+    Column::new("region_free", |_, info| {
+        info.header
+            .homebrew()
+            .map(|flags| flags.region_free.to_string())
+            .unwrap_or_default()
+    }),
+    Column::new("cic", |_, info| info.cic_info.name()),
+    Column::new("checksum_ok", |_, info| {
+        info.checksum
+            .map(|check| check.is_ok().to_string())
+            .unwrap_or_default()
+    }),
+    Column::new("corrected_entrypoint", |_, info| {
+        format!("{:X}", info.entrypoint)
+    }),
+    Column::new("entrypoint_length", |_, info| {
+        format!("{:X}", info.entrypoint_info.length)
+    }),
+    Column::new("initial_sp", |_, info| {
+        format!("{:X}", info.entrypoint_info.initial_sp)
+    }),
+    Column::new("bss_start", |_, info| {
+        format!("{:X}", info.entrypoint_info.bss_start)
+    }),
+    Column::new("bss_size", |_, info| {
+        format!("{:X}", info.entrypoint_info.bss_size)
+    }),
+    Column::new("jump_addr", |_, info| {
+        format!("{:X}", info.entrypoint_info.jump_addr)
+    }),
+    Column::new("sp_op", |_, info| {
+        format!("{:?}", info.entrypoint_info.sp_op)
+    }),
+    Column::new("bss_start_op", |_, info| {
+        format!("{:?}", info.entrypoint_info.bss_start_op)
+    }),
+    Column::new("bss_size_op", |_, info| {
+        format!("{:?}", info.entrypoint_info.bss_size_op)
+    }),
+    Column::new("jump_kind", |_, info| {
+        info.entrypoint_info.jump_kind.to_string()
+    }),
+    Column::new("final_delay_slot", |_, info| {
+        info.entrypoint_info.final_delay_slot.to_string()
+    }),
+    Column::new("has_break", |_, info| {
+        info.entrypoint_info.has_break.to_string()
+    }),
+    Column::new("text_end", |_, info| {
+        info.compiler
+            .text_end
+            .map(|end| format!("{end:X}"))
+            .unwrap_or_default()
+    }),
+    Column::new("b_count", |_, info| info.compiler.b_count.to_string()),
+    Column::new("j_count", |_, info| info.compiler.j_count.to_string()),
+    Column::new("compiler", |_, info| info.compiler.compiler().to_string()),
+    Column::new("good_name", |_, info| {
+        info.metadata
+            .as_ref()
+            .and_then(|entry| entry.good_name.clone())
+            .unwrap_or_default()
+    }),
+    Column::new("db_save_type", |_, info| {
+        info.metadata
+            .as_ref()
+            .and_then(|entry| entry.save_type.clone())
+            .unwrap_or_default()
+    }),
+    Column::new("players", |_, info| {
+        let players = info.metadata.as_ref().and_then(|entry| entry.players);
+        players
+            .map(|players| players.to_string())
+            .unwrap_or_default()
+    }),
+    Column::new("rumble", |_, info| {
+        let rumble = info.metadata.as_ref().and_then(|entry| entry.rumble);
+        rumble.map(|rumble| rumble.to_string()).unwrap_or_default()
+    }),
+    Column::new("transfer_pak", |_, info| {
+        let transfer_pak = info.metadata.as_ref().and_then(|entry| entry.transfer_pak);
+        transfer_pak
+            .map(|transfer_pak| transfer_pak.to_string())
+            .unwrap_or_default()
+    }),
+];
+
+/// Look up a comma-separated list of column names, e.g. from a `--fields` option
+pub fn select_columns(fields: &str) -> Result<Vec<&'static Column>> {
+    fields
+        .split(',')
+        .map(str::trim)
+        .map(|field| {
+            COLUMNS
+                .iter()
+                .find(|column| column.name == field)
+                .ok_or_else(|| Error::UnknownField(field.to_string()))
+        })
+        .collect()
+}
+
+/// Quote a field if it contains a delimiter, quote or line break, doubling any quotes
+pub fn escape_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Writes a header row followed by one record per ROM
+pub struct CsvWriter<W: io::Write> {
+    writer: W,
+    columns: Vec<&'static Column>,
+    header_written: bool,
+}
+
+impl<W: io::Write> CsvWriter<W> {
+    pub fn new(writer: W, columns: Vec<&'static Column>) -> CsvWriter<W> {
+        CsvWriter {
+            writer,
+            columns,
+            header_written: false,
+        }
+    }
+
+    fn write_record<'a>(&mut self, fields: impl Iterator<Item = Cow<'a, str>>) -> io::Result<()> {
+        let record: Vec<_> = fields
+            .map(|field| escape_field(&field).into_owned())
+            .collect();
+        write!(self.writer, "{}\r\n", record.join(","))
+    }
+
+    pub fn write_header(&mut self) -> io::Result<()> {
+        let names: Vec<_> = self
+            .columns
+            .iter()
+            .map(|column| Cow::Borrowed(column.name))
+            .collect();
+        self.write_record(names.into_iter())?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    /// Write one ROM's record, preceded by the header row if it has not been written yet
+    pub fn write_rom(&mut self, file_name: &str, info: &RomInfo) -> io::Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+        let values: Vec<_> = self
+            .columns
+            .iter()
+            .map(|column| Cow::Owned(column.value(file_name, info)))
+            .collect();
+        self.write_record(values.into_iter())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyze, Rom};
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_field("plain"), "plain");
+        assert_eq!(escape_field("a,b"), "\"a,b\"");
+        assert_eq!(escape_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn selecting_columns() {
+        let columns = select_columns("file, cic,image_name").unwrap();
+        let names: Vec<_> = columns.iter().map(|column| column.name).collect();
+        assert_eq!(names, ["file", "cic", "image_name"]);
+        assert!(matches!(
+            select_columns("file,bogus"),
+            Err(Error::UnknownField(field)) if field == "bogus"
+        ));
+    }
+
+    /// A ROM with a bare entrypoint and an image name that needs quoting
+    fn rom() -> Rom {
+        let mut data = vec![0; 0x2000];
+        data[0x00..0x04].copy_from_slice(&[0x80, 0x37, 0x12, 0x40]);
+        data[0x08..0x0C].copy_from_slice(&0x80000400u32.to_be_bytes());
+        data[0x20..0x34].copy_from_slice(b"A,B \"C\"\nD           ");
+        let entrypoint: [u32; 15] = [
+            0x3C088004, 0x2508E940, 0x24095D50, 0x2129FFF8, 0xAD000000, 0xAD000004, 0x1520FFFC,
+            0x21080008, 0x3C0A8002, 0x3C1D8004, 0x254A5CC0, 0x01400008, 0x27BDF330, 0, 0,
+        ];
+        for (chunk, word) in data[0x1000..].chunks_exact_mut(4).zip(entrypoint) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        Rom::from_bytes(data).unwrap()
+    }
+
+    #[test]
+    fn header_row_once() {
+        let info = analyze(&rom()).unwrap();
+        let mut output = Vec::new();
+        let mut writer = CsvWriter::new(&mut output, select_columns("file,image_name").unwrap());
+        writer.write_rom("a.z64", &info).unwrap();
+        writer.write_rom("b,c.z64", &info).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "file,image_name\r\n\
+             a.z64,\"A,B \"\"C\"\"\nD\"\r\n\
+             \"b,c.z64\",\"A,B \"\"C\"\"\nD\"\r\n"
+        );
+    }
+
+    #[test]
+    fn unprintable_libultra_version() {
+        let row = |rom: &Rom| {
+            let mut output = Vec::new();
+            let columns = select_columns("revision,libultra_version").unwrap();
+            let mut writer = CsvWriter::new(&mut output, columns);
+            writer.write_rom("a.z64", &analyze(rom).unwrap()).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(row(&rom()), "revision,libultra_version\r\n00000000,\r\n");
+
+        let mut data = rom().to_original_bytes();
+        data[0x0C..0x10].copy_from_slice(&0x144Bu32.to_be_bytes());
+        assert!(row(&Rom::from_bytes(data).unwrap()).ends_with("0000144B,K\r\n"));
+    }
+}
@@ -0,0 +1,327 @@
+//! 64DD disk images: the system area, disk ID and the IPL boot code the 64DD loads from the disk
+//!
+//! Only zone 0 (the outermost tracks of head 0) is needed for all of these, where every block
+//! is 85 sectors of 232 bytes. Two layouts are understood: `.ndd` dumps, which store each
+//! track's two blocks in physical order, and images stored in LBA order.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::compiler::{self, CompilerGuess};
+use crate::error::{Error, Result};
+use crate::n64header::entrypoint::{self, EntrypointInfo};
+
+/// Bytes in each zone 0 block
+pub const BLOCK_SIZE: usize = 85 * 232;
+/// Size of a full `.ndd` dump
+pub const NDD_SIZE: usize = 0x3DEC800;
+/// Zone 0 has 146 tracks of two blocks
+const ZONE_0_BLOCKS: u32 = 146 * 2;
+
+/// Retail system data is repeated in these blocks, in 232-byte sectors
+const RETAIL_SYSTEM_LBAS: [u32; 4] = [0, 1, 8, 9];
+/// Development disks keep theirs here, in 192-byte sectors
+const DEV_SYSTEM_LBAS: [u32; 4] = [2, 3, 10, 11];
+const DISK_ID_LBAS: [u32; 2] = [14, 15];
+/// First block of the IPL boot code
+const IPL_LBA: u32 = 24;
+
+/// How the blocks of a disk are ordered in the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Each track's blocks in physical order, as in `.ndd` dumps
+    Physical,
+    /// Blocks in LBA order
+    Lba,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum Region {
+    Japan,
+    #[serde(rename = "USA")]
+    Usa,
+    Development,
+}
+
+impl Region {
+    const fn from_magic(magic: u32) -> Option<Region> {
+        match magic {
+            0xE848D316 => Some(Region::Japan),
+            0x2263EE56 => Some(Region::Usa),
+            0x00000000 => Some(Region::Development),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Region::Japan => "Japan",
+                Region::Usa => "USA",
+                Region::Development => "development",
+            }
+        )
+    }
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes(bytes[..2].try_into().unwrap())
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+/// The system data sector, describing the disk's format and where its IPL boot code goes
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct SystemArea {
+    pub region: Region,
+    /// Disk type 0 to 6, which decides how much of the disk is ROM and how much is RAM
+    pub disk_type: u8,
+    /// Number of blocks of IPL boot code, starting at LBA 24
+    pub ipl_blocks: u16,
+    /// RDRAM address the IPL boot code is loaded to and run from
+    pub ipl_load_address: u32,
+    /// The LBA fields past the end of a development disk's 192-byte sector are `None`
+    pub rom_end_lba: Option<u16>,
+    pub ram_start_lba: Option<u16>,
+    pub ram_end_lba: Option<u16>,
+}
+
+impl SystemArea {
+    fn parse(sector: &[u8]) -> Option<SystemArea> {
+        let region = Region::from_magic(be_u32(&sector[0x00..]))?;
+        let format = sector[0x04];
+        if format >> 4 != 1 || format & 0x0F > 6 {
+            return None;
+        }
+        Some(SystemArea {
+            region,
+            disk_type: format & 0x0F,
+            ipl_blocks: be_u16(&sector[0x06..]),
+            ipl_load_address: be_u32(&sector[0x1C..]),
+            rom_end_lba: sector.get(0xE0..0xE2).map(be_u16),
+            ram_start_lba: sector.get(0xE2..0xE4).map(be_u16),
+            ram_end_lba: sector.get(0xE4..0xE6).map(be_u16),
+        })
+    }
+}
+
+/// The disk ID sector, written when the disk was manufactured
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DiskId {
+    /// Four-character game code, like a cartridge's ID with a prefix and country code
+    pub game_code: String,
+    pub version: u8,
+    pub disk_number: u8,
+    pub company_code: String,
+}
+
+impl DiskId {
+    fn parse(sector: &[u8]) -> DiskId {
+        DiskId {
+            game_code: String::from_utf8_lossy(&sector[0x00..0x04]).to_string(),
+            version: sector[0x04],
+            disk_number: sector[0x05],
+            company_code: String::from_utf8_lossy(&sector[0x18..0x1A]).to_string(),
+        }
+    }
+}
+
+/// A whole 64DD disk image held in memory
+pub struct DiskImage {
+    data: Vec<u8>,
+    layout: Layout,
+    system: SystemArea,
+    disk_id: DiskId,
+}
+
+impl DiskImage {
+    pub fn from_bytes(data: Vec<u8>) -> Result<DiskImage> {
+        let layout = if data.len() == NDD_SIZE {
+            Layout::Physical
+        } else {
+            Layout::Lba
+        };
+        let read_block = |lba| block(&data, layout, lba);
+
+        // Use the first readable copy of the system data
+        let system = RETAIL_SYSTEM_LBAS
+            .iter()
+            .filter_map(|&lba| read_block(lba).ok())
+            .filter_map(|sector| SystemArea::parse(&sector[..0xE8]))
+            .find(|system| system.region != Region::Development)
+            .or_else(|| {
+                DEV_SYSTEM_LBAS
+                    .iter()
+                    .filter_map(|&lba| read_block(lba).ok())
+                    .filter_map(|sector| SystemArea::parse(&sector[..0xC0]))
+                    .find(|system| system.region == Region::Development)
+            })
+            .ok_or(Error::InvalidDisk("no valid system area"))?;
+        let disk_id = DISK_ID_LBAS
+            .iter()
+            .find_map(|&lba| read_block(lba).ok())
+            .map(|sector| DiskId::parse(&sector[..0xE8]))
+            .ok_or(Error::InvalidDisk("no disk ID"))?;
+
+        Ok(DiskImage {
+            data,
+            layout,
+            system,
+            disk_id,
+        })
+    }
+
+    pub fn load(mut reader: impl Read) -> Result<DiskImage> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        DiskImage::from_bytes(data)
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<DiskImage> {
+        DiskImage::load(File::open(path)?)
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn system(&self) -> &SystemArea {
+        &self.system
+    }
+
+    pub fn disk_id(&self) -> &DiskId {
+        &self.disk_id
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    /// The IPL boot code, gathered from its blocks into the order it is loaded in
+    pub fn ipl_boot_code(&self) -> Result<Vec<u8>> {
+        if self.system.ipl_blocks == 0 {
+            return Err(Error::InvalidDisk("no IPL boot code"));
+        }
+        if IPL_LBA + self.system.ipl_blocks as u32 > ZONE_0_BLOCKS {
+            return Err(Error::InvalidDisk("IPL boot code extends past zone 0"));
+        }
+        let mut code = Vec::with_capacity(self.system.ipl_blocks as usize * BLOCK_SIZE);
+        for lba in IPL_LBA..IPL_LBA + self.system.ipl_blocks as u32 {
+            code.extend_from_slice(block(&self.data, self.layout, lba)?);
+        }
+        Ok(code)
+    }
+}
+
+/// The zone 0 block at `lba`
+fn block(data: &[u8], layout: Layout, lba: u32) -> Result<&[u8]> {
+    if lba >= ZONE_0_BLOCKS {
+        return Err(Error::InvalidDisk("IPL boot code extends past zone 0"));
+    }
+    let index = match layout {
+        // LBAs run forwards through a track's blocks on even tracks and backwards on odd ones
+        Layout::Physical => {
+            let track = lba / 2;
+            track * 2 + ((lba % 2) ^ (track % 2))
+        }
+        Layout::Lba => lba,
+    };
+    let offset = index as usize * BLOCK_SIZE;
+    data.get(offset..offset + BLOCK_SIZE)
+        .ok_or(Error::TruncatedRom {
+            offset: offset as u64,
+            len: BLOCK_SIZE,
+        })
+}
+
+/// Everything we know about a disk after running the analysis on its IPL boot code
+#[derive(serde::Serialize)]
+pub struct DiskInfo {
+    pub file_size: u64,
+    pub layout: Layout,
+    pub system: SystemArea,
+    pub disk_id: DiskId,
+    pub entrypoint_info: EntrypointInfo,
+    pub compiler: CompilerGuess,
+    /// Anything unusual found that did not stop the analysis
+    pub warnings: Vec<String>,
+}
+
+/// Run the entrypoint and compiler analysis on a disk's IPL boot code
+pub fn analyze(disk: &DiskImage) -> Result<DiskInfo> {
+    let code = disk.ipl_boot_code()?;
+    let system = *disk.system();
+    let entrypoint_info = entrypoint::parse(&code[..0x100], system.ipl_load_address)?;
+    let compiler = compiler::guess_gcc_or_ido(&code, 0);
+
+    let mut warnings = Vec::new();
+    warnings.extend(entrypoint_info.warning());
+    if compiler.text_end.is_none() {
+        warnings.push("No functions found in IPL boot code".to_string());
+    }
+
+    Ok(DiskInfo {
+        file_size: disk.file_size(),
+        layout: disk.layout(),
+        system,
+        disk_id: disk.disk_id().clone(),
+        entrypoint_info,
+        compiler,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An image in LBA order with nothing but a development system area in block 2
+    fn dev_disk() -> Vec<u8> {
+        let mut data = vec![0; 16 * BLOCK_SIZE];
+        let sector = &mut data[2 * BLOCK_SIZE..];
+        sector[0x04] = 0x13;
+        sector[0x06..0x08].copy_from_slice(&1u16.to_be_bytes());
+        sector[0x1C..0x20].copy_from_slice(&0x80000400u32.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn dev_system_area() {
+        let disk = DiskImage::from_bytes(dev_disk()).unwrap();
+        let system = disk.system();
+        assert_eq!(disk.layout(), Layout::Lba);
+        assert_eq!(system.region, Region::Development);
+        assert_eq!(system.disk_type, 3);
+        assert_eq!(system.ipl_blocks, 1);
+        assert_eq!(system.ipl_load_address, 0x80000400);
+        assert_eq!(system.rom_end_lba, None);
+    }
+
+    #[test]
+    fn ipl_past_zone_0() {
+        let mut data = dev_disk();
+        data[2 * BLOCK_SIZE + 0x06..2 * BLOCK_SIZE + 0x08]
+            .copy_from_slice(&0xFFFFu16.to_be_bytes());
+        let disk = DiskImage::from_bytes(data).unwrap();
+        assert!(matches!(disk.ipl_boot_code(), Err(Error::InvalidDisk(_))));
+    }
+
+    #[test]
+    fn retail_system_area() {
+        let mut sector = vec![0; 0xE8];
+        sector[0x00..0x04].copy_from_slice(&0xE848D316u32.to_be_bytes());
+        sector[0x04] = 0x10;
+        sector[0xE0..0xE2].copy_from_slice(&1417u16.to_be_bytes());
+        let system = SystemArea::parse(&sector).unwrap();
+        assert_eq!(system.region, Region::Japan);
+        assert_eq!(system.rom_end_lba, Some(1417));
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+use std::io;
+
+use crate::n64header::detect::Confidence;
+
+/// Everything that can go wrong while reading or analysing a ROM
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// First word is not the PI magic in any known byte order
+    UnknownByteOrder([u8; 4]),
+    /// Byte order was only guessed with this confidence, too unsure to write the ROM back in
+    UncertainByteOrder(Confidence),
+    /// ROM ended before `len` bytes could be read at `offset`
+    TruncatedRom {
+        offset: u64,
+        len: usize,
+    },
+    /// IPL3 CRC does not match any known CIC
+    UnknownCic(u32),
+    /// The named boot path does not check a boot checksum, so there is none to compute
+    NoBootChecksum(&'static str),
+    /// Entrypoint function could not be understood
+    UnparseableEntrypoint(&'static str),
+    /// Requested a CSV column that does not exist
+    UnknownField(String),
+    /// Value cannot be stored in the named header field
+    InvalidHeaderField(&'static str, String),
+    /// Metadata database could not be parsed at the given line
+    MalformedMetadata(usize, String),
+    /// 64DD disk image is missing something every disk has
+    InvalidDisk(&'static str),
+    /// IPS or BPS patch is malformed or does not fit the ROM
+    InvalidPatch(&'static str),
+    /// Analysis hit a bug and panicked, with the panic message
+    Panicked(String),
+    /// BPS CRC32 of the source, target or patch itself is not what the patch records
+    PatchCrcMismatch {
+        kind: &'static str,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{err}"),
+            Error::UnknownByteOrder(magic) => write!(
+                f,
+                "unrecognised header format {:02X} {:02X} {:02X} {:02X}",
+                magic[0], magic[1], magic[2], magic[3]
+            ),
+            Error::UncertainByteOrder(confidence) => write!(
+                f,
+                "byte order is only a {confidence} confidence guess, refusing to write the ROM"
+            ),
+            Error::TruncatedRom { offset, len } => write!(
+                f,
+                "ROM is truncated: could not read {len:#X} bytes at {offset:#X}"
+            ),
+            Error::UnknownCic(crc) => write!(f, "unknown CIC (IPL3 CRC {crc:08X})"),
+            Error::NoBootChecksum(boot) => write!(f, "{boot} images have no boot checksum"),
+            Error::UnparseableEntrypoint(reason) => {
+                write!(f, "could not parse entrypoint: {reason}")
+            }
+            Error::UnknownField(field) => write!(f, "unknown CSV field \"{field}\""),
+            Error::InvalidHeaderField(field, reason) => write!(f, "invalid {field}: {reason}"),
+            Error::InvalidDisk(reason) => write!(f, "invalid 64DD disk image: {reason}"),
+            Error::Panicked(message) => write!(f, "internal error: {message}"),
+            Error::InvalidPatch(reason) => write!(f, "invalid patch: {reason}"),
+            Error::PatchCrcMismatch {
+                kind,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{kind} CRC32 is {actual:08X}, but the patch expects {expected:08X}"
+            ),
+            Error::MalformedMetadata(line, reason) => {
+                write!(f, "malformed metadata database at line {line}: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Io(err.into())
+    }
+}
@@ -0,0 +1,184 @@
+pub mod compiler;
+pub mod convert;
+pub mod csv;
+pub mod disk;
+pub mod error;
+pub mod metadata;
+pub mod mips;
+pub mod n64header;
+pub mod patch;
+pub mod reend;
+pub mod rom;
+pub mod size;
+#[cfg(test)]
+mod test_util;
+
+pub use error::{Error, Result};
+pub use reend::ReendReader;
+pub use rom::Rom;
+
+use compiler::CompilerGuess;
+use metadata::GameMetadata;
+use mips::MipsGpr;
+use n64header::detect::Confidence;
+use n64header::entrypoint::{self, EntrypointInfo};
+use n64header::ipl3::{CICInfo, ChecksumCheck};
+use n64header::libultra::{self, LibultraVersion};
+use n64header::lint::Severity;
+use n64header::{Endian, N64Header};
+use size::SizeInfo;
+
+/// Re-ends an array in-place
+pub fn reend_array(v: &mut [u8], endian: &Endian) {
+    let n = v.len();
+    assert!(n % 4 == 0);
+    match endian {
+        Endian::Good => (),
+        Endian::Bad => {
+            for chunk in v.chunks_exact_mut(4) {
+                chunk.reverse();
+            }
+        }
+        Endian::Ugly => {
+            for chunk in v.chunks_exact_mut(2) {
+                chunk.reverse();
+            }
+        }
+    };
+}
+
+pub(crate) struct MyInstruction {
+    pub(crate) instr: rabbitizer::Instruction,
+}
+
+impl MyInstruction {
+    pub(crate) fn instr_get_rs(&self) -> MipsGpr {
+        ((self.instr.raw() >> 21) & 0x1F).try_into().unwrap()
+    }
+    pub(crate) fn instr_get_rt(&self) -> MipsGpr {
+        ((self.instr.raw() >> 16) & 0x1F).try_into().unwrap()
+    }
+    #[allow(dead_code)]
+    pub(crate) fn instr_get_rd(&self) -> MipsGpr {
+        ((self.instr.raw() >> 11) & 0x1F).try_into().unwrap()
+    }
+}
+
+/// Disassemble big-endian `data`, which is loaded at `vram`, giving the address, raw word and text
+/// of each instruction
+pub fn disassemble(data: &[u8], vram: u32) -> Vec<(u32, u32, String)> {
+    data.chunks_exact(4)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let address = vram.wrapping_add(4 * i as u32);
+            let word = u32::from_be_bytes(chunk.try_into().unwrap());
+            let instr = rabbitizer::Instruction::new(word, address);
+            (address, word, instr.disassemble(None, 0))
+        })
+        .collect()
+}
+
+/// Everything we know about a ROM after running the full analysis
+#[derive(serde::Serialize)]
+pub struct RomInfo {
+    pub file_size: u64,
+    pub size: SizeInfo,
+    pub endian: Endian,
+    pub endian_confidence: Confidence,
+    pub header: N64Header,
+    /// The header's libultra release, with the iQue build told apart by the CIC
+    pub libultra: LibultraVersion,
+    pub cic_info: CICInfo,
+    /// Entrypoint after correcting for the CIC's offset
+    pub entrypoint: u32,
+    pub entrypoint_info: EntrypointInfo,
+    /// Boot checksum check, if the CIC is known, boots with one and the ROM is long enough
+    pub checksum: Option<ChecksumCheck>,
+    pub compiler: CompilerGuess,
+    /// Matching entry from a metadata database, if one was given
+    pub metadata: Option<GameMetadata>,
+    /// Anything unusual found that did not stop the analysis
+    pub warnings: Vec<String>,
+}
+
+/// Parse the entrypoint function at the start of the boot segment
+pub fn parse_entrypoint(rom: &Rom) -> Result<EntrypointInfo> {
+    entrypoint::parse(rom.slice(rom::BOOT_SEGMENT_START, 0x100)?, rom.entrypoint())
+}
+
+/// Run the full analysis pipeline on a ROM: endian, header, CIC, entrypoint and compiler guess
+pub fn analyze(rom: &Rom) -> Result<RomInfo> {
+    let entrypoint = rom.entrypoint();
+    let entrypoint_info = parse_entrypoint(rom)?;
+    let bss_start = entrypoint_info.bss_start;
+
+    // Guess GCC vs IDO, using everything before bss if we know where that is
+    let boot_size = if bss_start > entrypoint {
+        (bss_start - entrypoint) as usize
+    } else {
+        rom::BOOT_SEGMENT_SIZE as usize
+    };
+    let code = &rom.data()[rom::BOOT_SEGMENT_START as usize..];
+    let compiler = compiler::guess_gcc_or_ido(
+        &code[..boot_size.min(code.len())],
+        rom::BOOT_SEGMENT_START as usize,
+    );
+
+    let cic_info = rom.cic_info().clone();
+    let mut warnings = Vec::new();
+    let endian_guess = rom.endian_guess();
+    if endian_guess.confidence != Confidence::Exact {
+        warnings.push(format!(
+            "Non-standard PI magic, byte order guessed as {} with {} confidence",
+            endian_guess.endian.extension(),
+            endian_guess.confidence
+        ));
+    }
+    if !cic_info.is_known() {
+        warnings.push(format!(
+            "Unknown CIC (IPL3 CRC {:08X})",
+            cic_info.ipl3_crc()
+        ));
+    }
+    warnings.extend(
+        rom.header()
+            .lint()
+            .into_iter()
+            .filter(|finding| finding.severity >= Severity::Warning)
+            .map(|finding| format!("Header {}: {}", finding.field, finding.message)),
+    );
+    warnings.extend(rom.header().timing_warnings());
+    let size = size::analyze(rom.data(), rom.file_size());
+    warnings.extend(size.warnings());
+    let checksum = rom.check_checksum().ok();
+    if let Some(check) = checksum.filter(|check| !check.is_ok()) {
+        warnings.push(format!("Checksum {check}"));
+    }
+    warnings.extend(entrypoint_info.warning());
+    let ram_end = if rom.cic_info().is_ique() {
+        entrypoint::IQUE_RAM_END
+    } else {
+        entrypoint::N64_RAM_END
+    };
+    warnings.extend(entrypoint_info.stack_warning(ram_end));
+    if compiler.text_end.is_none() {
+        warnings.push("No functions found in boot segment".to_string());
+    }
+    warnings.extend(libultra::check_sdk_build(&rom.libultra(), &compiler));
+
+    Ok(RomInfo {
+        file_size: rom.file_size(),
+        size,
+        endian: rom.endian(),
+        endian_confidence: rom.endian_guess().confidence,
+        header: rom.header().clone(),
+        libultra: rom.libultra(),
+        cic_info,
+        entrypoint,
+        entrypoint_info,
+        checksum,
+        compiler,
+        metadata: None,
+        warnings,
+    })
+}
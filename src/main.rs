@@ -1,252 +1,1237 @@
-mod mips;
-mod n64header;
-use mips::MipsGpr;
 use std::{
-    env,
+    borrow::Cow,
     fs::File,
-    io::{self, Read, Seek, SeekFrom, Write},
+    io::{self, IsTerminal, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-// use mips::disassemble_word;
-use n64header::entrypoint;
-use n64header::ipl3;
-use n64header::Endian;
-
-const VERBOSE: bool = false;
-
-// const DATA: &[u32] = &[
-//     0x0C000001,
-//     0,
-//     0
-// ];
-const DATA: &[u32] = &[
-    0x3C088004, 0x2508E940, 0x24095D50, 0x2129FFF8, 0xAD000000, 0xAD000004, 0x1520FFFC, 0x21080008,
-    0x3C0A8002, 0x3C1D8004, 0x254A5CC0, 0x01400008, 0x27BDF330, 0x00000000, 0x00000000,
-];
-
-fn bytes_to_reend_word(bytes: &[u8], endian: &Endian) -> u32 {
-    assert!(bytes.len() >= 4);
-    match endian {
-        Endian::Good => u32::from_be_bytes(bytes.try_into().unwrap()),
-        Endian::Bad => u32::from_le_bytes(bytes.try_into().unwrap()),
-        Endian::Ugly => u32::from_be_bytes([bytes[1], bytes[0], bytes[3], bytes[2]]),
-    }
-}
-
-fn bytes_to_reend_bytes(bytes: &[u8; 4], endian: &Endian) -> [u8; 4] {
-    match endian {
-        Endian::Good => *bytes,
-        Endian::Bad => [bytes[3], bytes[2], bytes[1], bytes[0]],
-        Endian::Ugly => [bytes[1], bytes[0], bytes[3], bytes[2]],
-    }
-}
-
-/// Re-ends an array in-place
-pub fn reend_array(v: &mut [u8], endian: &Endian) {
-    let n = v.len();
-    assert!(n % 4 == 0);
-    match endian {
-        Endian::Good => (),
-        Endian::Bad => {
-            for chunk in v.chunks_exact_mut(4) {
-                chunk.reverse();
-            }
+use clap::{Parser, Subcommand, ValueEnum};
+
+use bunny_oxide::compiler::Compiler;
+use bunny_oxide::convert;
+use bunny_oxide::csv::{self, CsvWriter};
+use bunny_oxide::disk::{self, DiskImage, DiskInfo};
+use bunny_oxide::metadata::{GameMetadata, MetadataDb};
+use bunny_oxide::n64header::detect::{self, Confidence};
+use bunny_oxide::n64header::entrypoint::{DelaySlot, EntrypointInfo};
+use bunny_oxide::n64header::homebrew::{HomebrewFlags, SaveType};
+use bunny_oxide::n64header::ipl3::{CICInfo, ChecksumCheck};
+use bunny_oxide::n64header::lint::{Finding, Severity};
+use bunny_oxide::n64header::{self, Endian, N64Header};
+use bunny_oxide::patch::{self, PatchFormat};
+use bunny_oxide::size;
+use bunny_oxide::{Error, ReendReader, Result, Rom, RomInfo};
+use rayon::prelude::*;
+use walkdir::WalkDir;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "N64 ROM analysis tool",
+    after_help = "Exit status is 0 if every ROM was ok, 1 if some had warnings, 2 if the arguments \
+                  were invalid, 3 if some failed"
+)]
+struct Cli {
+    /// Show more detail; repeat for tracing
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Show less detail; repeat to show only errors
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    quiet: u8,
+    /// Per-module log levels, e.g. `bunny_oxide::n64header::entrypoint=trace`
+    #[arg(long, global = true)]
+    log: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the full analysis: header, CIC, entrypoint and compiler guess
+    Info {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        /// Comma-separated list of columns for CSV output
+        #[arg(long)]
+        fields: Option<String>,
+        /// mupen64plus.ini-style database to look up each ROM's metadata in
+        #[arg(long)]
+        metadata: Option<PathBuf>,
+        /// ROM files, or directories to scan recursively for ROMs
+        #[arg(required = true)]
+        roms: Vec<PathBuf>,
+    },
+    /// Print the ROM header, or edit it with `header set`
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Header {
+        #[command(subcommand)]
+        action: Option<HeaderAction>,
+        #[arg(long, value_enum, default_value_t = DetailFormat::Text)]
+        format: DetailFormat,
+        /// ROM files, or directories to scan recursively for ROMs
+        #[arg(required = true)]
+        roms: Vec<PathBuf>,
+    },
+    /// Check every header field for anything suspicious
+    Lint {
+        #[arg(long, value_enum, default_value_t = DetailFormat::Text)]
+        format: DetailFormat,
+        /// ROM files, or directories to scan recursively for ROMs
+        #[arg(required = true)]
+        roms: Vec<PathBuf>,
+    },
+    /// Identify the CIC from the IPL3 and print the corrected entrypoint
+    Cic {
+        #[arg(long, value_enum, default_value_t = DetailFormat::Text)]
+        format: DetailFormat,
+        /// ROM files, or directories to scan recursively for ROMs
+        #[arg(required = true)]
+        roms: Vec<PathBuf>,
+    },
+    /// Verify the boot checksum stored in the header against the one IPL3 computes
+    Checksum {
+        #[arg(long, value_enum, default_value_t = DetailFormat::Text)]
+        format: DetailFormat,
+        /// ROM files, or directories to scan recursively for ROMs
+        #[arg(required = true)]
+        roms: Vec<PathBuf>,
+    },
+    /// Rewrite the header's boot checksum so the ROM boots on real hardware
+    FixChecksum {
+        /// Write even if the byte order is only a low or medium confidence guess
+        #[arg(long)]
+        force: bool,
+        /// Write the fixed ROM here instead of modifying it in place
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        rom: PathBuf,
+    },
+    /// Write a ROM in a different byte order
+    Convert {
+        /// Byte order to convert to, named after its usual file extension
+        #[arg(long, value_enum)]
+        to: ByteOrder,
+        /// Write even if the byte order is only a low or medium confidence guess
+        #[arg(long)]
+        force: bool,
+        /// Write the converted ROM here instead of converting it in place
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        rom: PathBuf,
+    },
+    /// Cut a ROM down to the smallest cart size its data fits in, removing overdumped copies
+    Trim {
+        /// Write even if the byte order is only a low or medium confidence guess
+        #[arg(long)]
+        force: bool,
+        /// Write the trimmed ROM here instead of modifying it in place
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        rom: PathBuf,
+    },
+    /// Pad a trimmed ROM out to the next cart size
+    Pad {
+        /// Byte to pad with, defaults to the ROM's existing padding or FF
+        #[arg(long, value_parser = parse_u8)]
+        fill: Option<u8>,
+        /// Write even if the byte order is only a low or medium confidence guess
+        #[arg(long)]
+        force: bool,
+        /// Write the padded ROM here instead of modifying it in place
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        rom: PathBuf,
+    },
+    /// Apply or create IPS and BPS patches, working in big-endian whatever the ROM's byte order
+    Patch {
+        #[command(subcommand)]
+        action: PatchAction,
+    },
+    /// Parse the entrypoint function: bss, initial stack pointer and main jump
+    Entry {
+        #[arg(long, value_enum, default_value_t = DetailFormat::Text)]
+        format: DetailFormat,
+        /// ROM files, or directories to scan recursively for ROMs
+        #[arg(required = true)]
+        roms: Vec<PathBuf>,
+    },
+    /// Analyse 64DD disk images: system area, disk ID and IPL boot code
+    Disk {
+        #[arg(long, value_enum, default_value_t = DetailFormat::Text)]
+        format: DetailFormat,
+        /// Disk images, or directories to scan recursively for .ndd and .d64 files
+        #[arg(required = true)]
+        disks: Vec<PathBuf>,
+    },
+    /// Disassemble part of a ROM
+    Disasm {
+        /// ROM offset to start from
+        #[arg(long, value_parser = parse_u32, default_value = "0x1000")]
+        start: u32,
+        /// Number of instructions to disassemble
+        #[arg(long, value_parser = parse_u32, default_value = "0x40")]
+        count: u32,
+        /// Address the code runs at, defaults to the corrected entrypoint for the boot segment
+        #[arg(long, value_parser = parse_u32)]
+        vram: Option<u32>,
+        rom: PathBuf,
+    },
+}
+
+/// Options of `header set` that switch to the advanced homebrew header, which takes over the
+/// cartridge ID and version
+const HOMEBREW_ARGS: [&str; 3] = ["save_type", "rtc", "region_free"];
+
+#[derive(Subcommand)]
+enum HeaderAction {
+    /// Change header fields, writing the ROM back in its original byte order
+    Set {
+        /// Internal name, up to 20 bytes once encoded as Shift-JIS
+        #[arg(long)]
+        image_name: Option<String>,
+        /// Two-character game ID. The advanced homebrew header stores its flags here instead.
+        #[arg(long, conflicts_with_all = HOMEBREW_ARGS)]
+        cartridge_id: Option<String>,
+        #[arg(long)]
+        country_code: Option<char>,
+        #[arg(long = "rom-version", value_parser = parse_u8, conflicts_with_all = HOMEBREW_ARGS)]
+        version: Option<u8>,
+        #[arg(long)]
+        media_format: Option<char>,
+        /// Save type for the advanced homebrew header, e.g. `eeprom4k` or `sram256k`
+        #[arg(long)]
+        save_type: Option<SaveType>,
+        /// Declare a real-time clock in the advanced homebrew header
+        #[arg(long)]
+        rtc: Option<bool>,
+        /// Declare the ROM region-free in the advanced homebrew header
+        #[arg(long)]
+        region_free: Option<bool>,
+        /// Write even if the byte order is only a low or medium confidence guess
+        #[arg(long)]
+        force: bool,
+        /// Write the edited ROM here instead of modifying it in place
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        rom: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum PatchAction {
+    /// Apply an IPS or BPS patch, writing the ROM back in its original byte order
+    Apply {
+        /// Recompute the boot checksum after patching
+        #[arg(long)]
+        fix_checksum: bool,
+        /// Write even if the byte order is only a low or medium confidence guess
+        #[arg(long)]
+        force: bool,
+        /// Write the patched ROM here instead of modifying it in place
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        rom: PathBuf,
+        patch: PathBuf,
+    },
+    /// Make a patch that turns one ROM into another
+    Create {
+        #[arg(long, value_enum, default_value_t = PatchKind::Bps)]
+        format: PatchKind,
+        /// Where to write the patch
+        #[arg(short, long)]
+        output: PathBuf,
+        original: PathBuf,
+        modified: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PatchKind {
+    /// International Patching System, up to 16 MiB with no checksums
+    Ips,
+    /// Beat patch, with CRC32s of the source, target and patch
+    Bps,
+}
+
+impl From<PatchKind> for PatchFormat {
+    fn from(kind: PatchKind) -> PatchFormat {
+        match kind {
+            PatchKind::Ips => PatchFormat::Ips,
+            PatchKind::Bps => PatchFormat::Bps,
         }
-        Endian::Ugly => {
-            for chunk in v.chunks_exact_mut(2) {
-                chunk.reverse();
-            }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ByteOrder {
+    /// Big-endian
+    Z64,
+    /// Byteswapped
+    V64,
+    /// Little-endian
+    N64,
+}
+
+impl From<ByteOrder> for Endian {
+    fn from(order: ByteOrder) -> Endian {
+        match order {
+            ByteOrder::Z64 => Endian::Good,
+            ByteOrder::V64 => Endian::Ugly,
+            ByteOrder::N64 => Endian::Bad,
         }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Semicolon-separated fragments
+    Text,
+    /// Long human-readable report
+    Long,
+    /// One JSON object per ROM, one per line
+    Json,
+    /// RFC 4180 CSV with a header row
+    Csv,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DetailFormat {
+    /// Human-readable
+    Text,
+    /// One JSON object per ROM, one per line
+    Json,
+}
+
+#[derive(serde::Serialize)]
+struct JsonReport<'a, T: serde::Serialize> {
+    file: &'a str,
+    #[serde(flatten)]
+    info: &'a T,
+}
+
+fn print_json<T: serde::Serialize>(base_name: &str, info: &T) -> Result<()> {
+    let report = JsonReport {
+        file: base_name,
+        info,
     };
+    println!("{}", serde_json::to_string(&report)?);
+    Ok(())
 }
 
-fn guess_gcc_or_ido(data: &[u8], endian: &Endian) {
-    let mut j_count = 0;
-    let mut b_count = 0;
+/// Record a ROM that could not be analysed, so batch JSON output covers every input
+fn print_failure_json(base_name: &str, err: &Error) -> Result<()> {
+    #[derive(serde::Serialize)]
+    struct Failure {
+        file: String,
+        failed: String,
+    }
 
-    if VERBOSE {
-        println!();
-        println!("Examining up to {:#X} bytes", data.len());
-    }
-
-    let mut in_function = false;
-    let mut text_end = None;
-    // let mut consecutive_nops = 0;
-    for (i, chunk) in data.chunks_exact(4).rev().enumerate() {
-        let word = bytes_to_reend_word(chunk, endian);
-        let instr = rabbitizer::Instruction::new(word, 0);
-
-        // if instr.is_nop() {
-        //     consecutive_nops += 1;
-        // } else {
-        //     consecutive_nops = 0;
-        // }
-        if instr.is_jr_ra() {
-            in_function = true;
-            if text_end.is_none() {
-                text_end = Some(0x1000 + data.len() - 4 * i);
-            }
-        } else if !instr.is_valid() {
-            in_function = false
-        }
+    println!(
+        "{}",
+        serde_json::to_string(&Failure {
+            file: base_name.to_string(),
+            failed: err.to_string(),
+        })?
+    );
+    Ok(())
+}
 
-        if in_function {
-            match instr.instr_id() {
-                rabbitizer::InstrId::RABBITIZER_INSTR_ID_cpu_b => b_count += 1,
-                rabbitizer::InstrId::RABBITIZER_INSTR_ID_cpu_j => j_count += 1,
-                _ => (),
-            }
-        }
+/// Parse a number, in hex if prefixed with `0x`
+fn parse_u32(s: &str) -> std::result::Result<u32, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
     }
+    .map_err(|e| e.to_string())
+}
 
-    if VERBOSE {
+fn parse_u8(s: &str) -> std::result::Result<u8, String> {
+    u8::try_from(parse_u32(s)?).map_err(|e| e.to_string())
+}
+
+/// File name for reports, with anything that is not UTF-8 replaced
+fn base_name(file_name: &Path) -> Cow<'_, str> {
+    file_name
+        .file_name()
+        .unwrap_or(file_name.as_os_str())
+        .to_string_lossy()
+}
+
+/// Print the full analysis of a ROM, returning its warnings
+fn report_info(
+    file_name: &Path,
+    info: RomInfo,
+    format: OutputFormat,
+    csv_writer: &mut CsvWriter<io::Stdout>,
+) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+    let header = &info.header;
+    let entry = &info.entrypoint_info;
+
+    if format == OutputFormat::Csv {
+        csv_writer.write_rom(&base_name, &info)?;
+    } else if format == OutputFormat::Json {
+        print_json(&base_name, &info)?;
+    } else if format == OutputFormat::Long {
+        println!("File: {base_name}");
         println!(
-            "Examined range 0x1000–{:#X} of boot segment",
-            text_end.unwrap()
+            "ROM size: 0x{:X} bytes ({} MB)",
+            info.file_size,
+            info.file_size / (1 << 20)
         );
-        println!("  B count:{b_count}");
-        println!("  J count:{j_count}");
+        println!("Contents: {}", info.size);
         println!();
-        if b_count + j_count < 100 {
-            println!("  Not enough to guess compiler");
-        } else if b_count > j_count {
-            println!("  Probably IDO");
-        } else {
-            println!("  Probably GCC");
+        println!("ROM Header:");
+        println!("{:#}", header);
+        println!();
+        println!("Libultra version: {}", info.libultra);
+        println!("CIC chip: {}", info.cic_info.name());
+        match info.checksum {
+            Some(check) => println!("Checksum: {check}"),
+            None => println!("Checksum: not checked"),
+        }
+        println!("Corrected entrypoint: {:X}", info.entrypoint);
+        print_entrypoint(entry);
+
+        if let Some(entry) = &info.metadata {
+            println!();
+            print_metadata(entry);
+        }
+
+        let guess = &info.compiler;
+        println!();
+        println!("Examining up to {:#X} bytes", guess.examined);
+        match guess.text_end {
+            Some(text_end) => println!("Examined range 0x1000–{text_end:#X} of boot segment"),
+            None => println!("No functions found in boot segment"),
+        }
+        println!("  B count:{}", guess.b_count);
+        println!("  J count:{}", guess.j_count);
+        println!();
+        match guess.compiler() {
+            Compiler::Unknown => println!("  Not enough to guess compiler"),
+            Compiler::Ido => println!("  Probably IDO"),
+            Compiler::Gcc => println!("  Probably GCC"),
         }
     } else {
-        print!("{:#X}; {b_count}; {j_count}; ", text_end.unwrap());
+        print!("{base_name}; ");
+        print!("{:X}; ", info.file_size);
+        print!("{}; {}; ", header, info.libultra);
+        print!("{}; ", info.cic_info.name());
+        print!("{:X}; ", info.entrypoint);
+        print!(
+            "{:X}; {:X}; {:X}; {:X}; {:X}; {:?}; {:?}; {:?}; {}; {}; {};",
+            entry.length,
+            entry.initial_sp,
+            entry.bss_start,
+            entry.bss_size,
+            entry.jump_addr,
+            entry.sp_op,
+            entry.bss_start_op,
+            entry.bss_size_op,
+            entry.jump_kind,
+            entry.final_delay_slot,
+            entry.has_break
+        );
+        print!(
+            "{:#X}; {}; {}; ",
+            info.compiler.text_end.unwrap_or(0),
+            info.compiler.b_count,
+            info.compiler.j_count
+        );
+        println!();
     }
+
+    Ok(info.warnings)
 }
 
-struct MyInstruction {
-    instr: rabbitizer::Instruction,
+fn print_metadata(entry: &GameMetadata) {
+    let yes_no = |flag: Option<bool>| match flag {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "unknown",
+    };
+    println!(
+        "Known as:       {}",
+        entry.good_name.as_deref().unwrap_or("unknown")
+    );
+    println!(
+        "Save type:      {}",
+        entry.save_type.as_deref().unwrap_or("unknown")
+    );
+    match entry.players {
+        Some(players) => println!("Players:        {players}"),
+        None => println!("Players:        unknown"),
+    }
+    println!("Rumble Pak:     {}", yes_no(entry.rumble));
+    println!("Transfer Pak:   {}", yes_no(entry.transfer_pak));
+    println!("Controller Pak: {}", yes_no(entry.controller_pak));
 }
 
-impl MyInstruction {
-    fn instr_get_rs(&self) -> MipsGpr {
-        ((self.instr.raw() >> 21) & 0x1F).try_into().unwrap()
+fn print_entrypoint(entry: &EntrypointInfo) {
+    match entry.final_delay_slot {
+        DelaySlot::Nop => println!("Final delay slot NOP. GCC assembler?"),
+        DelaySlot::Used => println!("Final delay slot used. IDO assembler?"),
+        DelaySlot::NotFound => (),
     }
-    fn instr_get_rt(&self) -> MipsGpr {
-        ((self.instr.raw() >> 16) & 0x1F).try_into().unwrap()
+    println!("jump to:    {:#010X}", entry.jump_addr);
+    println!("bss start:  {:#010X}", entry.bss_start);
+    println!("bss size:   {:#10X}", entry.bss_size);
+    println!("initial sp: {:#010X}", entry.initial_sp);
+}
+
+/// Read just the header, without loading the rest of the ROM
+fn read_header(file_name: &Path) -> Result<N64Header> {
+    let reader = ReendReader::new(File::open(file_name)?)?;
+    Ok(n64header::read_header(reader)?)
+}
+
+fn report_header(file_name: &Path, header: N64Header, format: DetailFormat) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+
+    match format {
+        DetailFormat::Json => print_json(&base_name, &header)?,
+        DetailFormat::Text => {
+            println!("File: {base_name}");
+            println!("{:#}", header);
+            println!();
+        }
     }
-    fn instr_get_rd(&self) -> MipsGpr {
-        ((self.instr.raw() >> 11) & 0x1F).try_into().unwrap()
+    Ok(Vec::new())
+}
+
+#[derive(serde::Serialize)]
+struct LintReport<'a> {
+    findings: &'a [Finding],
+}
+
+fn report_lint(
+    file_name: &Path,
+    findings: Vec<Finding>,
+    format: DetailFormat,
+) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+
+    match format {
+        DetailFormat::Json => print_json(
+            &base_name,
+            &LintReport {
+                findings: &findings,
+            },
+        )?,
+        DetailFormat::Text => {
+            for finding in &findings {
+                println!("{base_name}: {finding}");
+            }
+        }
     }
+    Ok(findings
+        .iter()
+        .filter(|finding| finding.severity >= Severity::Warning)
+        .map(|finding| format!("Header {}: {}", finding.field, finding.message))
+        .collect())
 }
 
-fn run(file_name: &String) -> Result<(), String> {
-    let base_name = file_name
-        .split('/')
-        .last()
-        .expect(format!("Invalid file name: {}", file_name).as_str());
+#[derive(serde::Serialize)]
+struct CicReport {
+    cic_info: CICInfo,
+    entrypoint: u32,
+}
 
-    if VERBOSE {
-        println!("File: {base_name}");
+fn identify_cic(file_name: &Path) -> Result<CicReport> {
+    let rom = Rom::open(file_name)?;
+    Ok(CicReport {
+        cic_info: rom.cic_info().clone(),
+        entrypoint: rom.entrypoint(),
+    })
+}
+
+fn report_cic(file_name: &Path, report: CicReport, format: DetailFormat) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+
+    match format {
+        DetailFormat::Json => print_json(&base_name, &report)?,
+        DetailFormat::Text => {
+            println!(
+                "{base_name}: CIC {}, corrected entrypoint {:08X}",
+                report.cic_info.name(),
+                report.entrypoint
+            );
+        }
+    }
+    if report.cic_info.is_known() {
+        Ok(Vec::new())
     } else {
-        print!("{base_name}; ");
+        Err(Error::UnknownCic(report.cic_info.ipl3_crc()))
     }
+}
 
-    let mut romfile = File::open(file_name).unwrap();
+fn check_checksum(file_name: &Path) -> Result<ChecksumCheck> {
+    Rom::open(file_name)?.check_checksum()
+}
 
-    let file_size = romfile.metadata().unwrap().len();
-    if VERBOSE {
-        println!(
-            "ROM size: 0x{file_size:X} bytes ({} MB)",
-            file_size / (1 << 20)
-        );
+fn report_checksum(
+    file_name: &Path,
+    check: ChecksumCheck,
+    format: DetailFormat,
+) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+
+    match format {
+        DetailFormat::Json => print_json(&base_name, &check)?,
+        DetailFormat::Text => println!("{base_name}: checksum {check}"),
+    }
+    if check.is_ok() {
+        Ok(Vec::new())
     } else {
-        print!("{file_size:X}; ");
-    }
-
-    // Determine endianness
-    let mut buffer = [0u8; 4];
-    romfile.read_exact(&mut buffer).unwrap();
-    let endian = n64header::get_endian(&buffer).unwrap();
-    // match endian {
-    //     Endian::Good => {
-    //         println!("Endian: {endian:?}");
-    //     }
-    //     _ => unimplemented!("Wordswapped and byteswapped ROMs are not currently supported")
-    // }
-    romfile.rewind().unwrap();
-
-    // Read header
-    let mut buffer = [0u8; 0x40];
-    romfile.read_exact(&mut buffer).unwrap();
-    reend_array(&mut buffer, &endian);
-    let header = n64header::read_header(&buffer[..]).expect("Failed to parse header");
-    if VERBOSE {
-        println!();
-        println!("ROM Header:");
-        println!("{:#}", header);
-        println!();
-        println!("Libultra version: {}", header.libultra_version().unwrap());
+        Ok(vec![format!("Checksum {check}")])
+    }
+}
+
+/// Open a ROM that is about to be written back in its own byte order, which has to be certain
+/// unless `force` is given
+fn open_for_writing(file_name: &Path, force: bool) -> Result<Rom> {
+    let rom = Rom::open(file_name)?;
+    if !force {
+        rom.endian_guess().require(Confidence::High)?;
+    }
+    Ok(rom)
+}
+
+fn fix_checksum(file_name: &Path, output: &Path, force: bool) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+    let mut rom = open_for_writing(file_name, force)?;
+    let check = rom.fix_checksum()?;
+
+    if check.is_ok() {
+        println!("{base_name}: checksum already OK");
+        if output != file_name {
+            rom.save(output)?;
+        }
+        return Ok(Vec::new());
+    }
+    rom.save(output)?;
+    println!(
+        "{base_name}: checksum {:08X} {:08X} replaced with {:08X} {:08X}",
+        check.stored.0, check.stored.1, check.calculated.0, check.calculated.1
+    );
+    Ok(Vec::new())
+}
+
+fn convert_rom(
+    file_name: &Path,
+    output: &Path,
+    to: Endian,
+    force: bool,
+    show_progress: bool,
+) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+    let needed = if force {
+        Confidence::Low
     } else {
-        print!("{}; {}; ", header, header.libultra_version().unwrap());
+        Confidence::High
+    };
+    let mut last_percent = None;
+    let from = convert::convert_file(file_name, output, to, needed, |written, total| {
+        let percent = (written * 100)
+            .checked_div(total)
+            .map_or(100, |percent| percent.min(100));
+        if show_progress && last_percent != Some(percent) {
+            eprint!("\r{base_name}: {percent}%");
+            last_percent = Some(percent);
+        }
+    })?;
+    if show_progress {
+        eprintln!();
     }
+    println!(
+        "{base_name}: converted from {} to {}",
+        from.extension(),
+        to.extension()
+    );
+    Ok(Vec::new())
+}
 
-    // Identify ipl3 and correct entrypoint
-    let cic_info = ipl3::identify(&romfile).unwrap();
-    let entrypoint = cic_info.correct_entrypoint(header.entrypoint());
-    if VERBOSE {
-        println!("CIC chip: {}", cic_info.name());
-        println!("Corrected entrypoint: {entrypoint:X}");
+/// Resize a ROM, fixing its boot checksum if the change reaches into the range it covers
+fn resize_rom(
+    file_name: &Path,
+    output: &Path,
+    trim: bool,
+    fill: Option<u8>,
+    force: bool,
+) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+    let rom = open_for_writing(file_name, force)?;
+    let size = size::analyze(rom.data(), rom.file_size());
+    let new_size = if trim {
+        size.cart_size
     } else {
-        print!("{}; ", cic_info.name());
-        print!("{entrypoint:X}; ");
+        size::cart_size_for(size.file_size)
+    };
+
+    if (trim && new_size >= size.file_size) || (!trim && new_size <= size.file_size) {
+        println!("{base_name}: already {:#X} bytes", size.file_size);
+        if output != file_name {
+            rom.save(output)?;
+        }
+        return Ok(Vec::new());
     }
 
-    // Parse entrypoint
-    let mut buffer = [0u8; 0x100];
-    romfile.read(&mut buffer).unwrap();
-    let (jump_addr, bss_start, bss_size, initial_sp) =
-        entrypoint::parse(&buffer, entrypoint, &endian, base_name);
+    let fill = fill.or(size.padding).unwrap_or(0xFF);
+    let mut resized = rom.resized(new_size as usize, fill)?;
+    let mut warnings = Vec::new();
+    if size::touches_checksum(size.file_size, new_size) {
+        match resized.fix_checksum() {
+            Ok(check) if !check.is_ok() => println!("{base_name}: checksum updated"),
+            Ok(_) => (),
+            Err(err) => warnings.push(format!("Could not fix checksum: {err}")),
+        }
+    }
+    resized.save(output)?;
+    println!(
+        "{base_name}: resized from {:#X} to {new_size:#X} bytes",
+        size.file_size
+    );
+    Ok(warnings)
+}
 
-    if VERBOSE {
-        println!("jump to:    {:#010X}", jump_addr);
-        println!("bss start:  {:#010X}", bss_start);
-        println!("bss size:   {:#10X}", bss_size);
-        println!("initial sp: {:#010X}", initial_sp);
+/// Apply a patch to the big-endian image, so it fits however the ROM is stored
+fn apply_patch(
+    file_name: &Path,
+    patch_file: &Path,
+    output: &Path,
+    fix_checksum: bool,
+    force: bool,
+) -> Result<Vec<String>> {
+    let patch_name = base_name(patch_file);
+    let base_name = base_name(file_name);
+    let rom = open_for_writing(file_name, force)?;
+    let patch = std::fs::read(patch_file)?;
+    let format = PatchFormat::detect(&patch)?;
+    let mut patched = rom.with_data(patch::apply(&patch, rom.unpadded_data())?)?;
+
+    let mut warnings = Vec::new();
+    if fix_checksum {
+        match patched.fix_checksum() {
+            Ok(check) if !check.is_ok() => println!("{base_name}: checksum updated"),
+            Ok(_) => (),
+            Err(err) => warnings.push(format!("Could not fix checksum: {err}")),
+        }
+    } else if let Ok(check) = patched.check_checksum() {
+        if !check.is_ok() {
+            warnings.push(format!("Checksum {check}"));
+        }
     }
+    patched.save(output)?;
+    println!(
+        "{base_name}: applied {} patch {patch_name}",
+        if format == PatchFormat::Ips {
+            "IPS"
+        } else {
+            "BPS"
+        }
+    );
+    Ok(warnings)
+}
 
-    // Guess GCC vs IDO
-    let mut buffer;
-    if bss_start > entrypoint {
-        let boot_size = (bss_start - entrypoint) as usize;
-        buffer = vec![0u8; boot_size];
-    } else {
-        buffer = vec![0u8; 0x100000];
+fn create_patch(
+    original: &Path,
+    modified: &Path,
+    output: &Path,
+    format: PatchFormat,
+) -> Result<Vec<String>> {
+    let source = Rom::open(original)?;
+    let target = Rom::open(modified)?;
+    let patch = patch::create(format, source.unpadded_data(), target.unpadded_data())?;
+    std::fs::write(output, &patch)?;
+    println!(
+        "{}: wrote {:#X} byte patch to {}",
+        base_name(modified),
+        patch.len(),
+        output.display()
+    );
+    Ok(Vec::new())
+}
+
+fn parse_entrypoint(file_name: &Path) -> Result<(u32, EntrypointInfo)> {
+    let rom = Rom::open(file_name)?;
+    Ok((rom.entrypoint(), bunny_oxide::parse_entrypoint(&rom)?))
+}
+
+fn report_entry(
+    file_name: &Path,
+    (entrypoint, entry): (u32, EntrypointInfo),
+    format: DetailFormat,
+) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+
+    match format {
+        DetailFormat::Json => print_json(&base_name, &entry)?,
+        DetailFormat::Text => {
+            println!("File: {base_name}");
+            println!("Corrected entrypoint: {entrypoint:X}");
+            println!("length:     {:#X}", entry.length);
+            print_entrypoint(&entry);
+            println!(
+                "sp built with {:?}, bss start in {} built with {:?}, bss size in {} built with {:?}",
+                entry.sp_op,
+                entry.bss_start_reg,
+                entry.bss_start_op,
+                entry.bss_size_reg,
+                entry.bss_size_op
+            );
+            println!("jumps with {} {}", entry.jump_kind, entry.jump_reg);
+            println!("break after entrypoint: {}", entry.has_break);
+            println!();
+        }
     }
-    romfile.seek(SeekFrom::Start(0x1000)).unwrap();
-    romfile.read(&mut buffer).unwrap();
-    guess_gcc_or_ido(&buffer, &endian);
+    Ok(entry.warning().into_iter().collect())
+}
 
-    if !VERBOSE {
-        println!();
+fn report_disk(file_name: &Path, info: DiskInfo, format: DetailFormat) -> Result<Vec<String>> {
+    let base_name = base_name(file_name);
+    let system = &info.system;
+    let disk_id = &info.disk_id;
+
+    match format {
+        DetailFormat::Json => print_json(&base_name, &info)?,
+        DetailFormat::Text => {
+            println!("File: {base_name}");
+            println!("Region: {}", system.region);
+            match (system.rom_end_lba, system.ram_start_lba, system.ram_end_lba) {
+                (Some(rom_end), Some(ram_start), Some(ram_end)) => println!(
+                    "Disk type: {} (ROM area ends at LBA {rom_end}, RAM area LBAs {ram_start}–{ram_end})",
+                    system.disk_type
+                ),
+                _ => println!("Disk type: {}", system.disk_type),
+            }
+            println!(
+                "Disk ID: {}, version {}, disk {}, company {}",
+                disk_id.game_code, disk_id.version, disk_id.disk_number, disk_id.company_code
+            );
+            println!(
+                "IPL boot code: {} blocks loaded to {:08X}",
+                system.ipl_blocks, system.ipl_load_address
+            );
+            print_entrypoint(&info.entrypoint_info);
+            println!("  B count:{}", info.compiler.b_count);
+            println!("  J count:{}", info.compiler.j_count);
+            match info.compiler.compiler() {
+                Compiler::Unknown => println!("  Not enough to guess compiler"),
+                Compiler::Ido => println!("  Probably IDO"),
+                Compiler::Gcc => println!("  Probably GCC"),
+            }
+            println!();
+        }
     }
+    Ok(info.warnings)
+}
 
-    Ok(())
+fn run_disasm(file_name: &Path, start: u32, count: u32, vram: Option<u32>) -> Result<Vec<String>> {
+    let rom = Rom::open(file_name)?;
+
+    let vram = vram
+        .or_else(|| rom.rom_to_vram(start))
+        // IPL3 runs from SP DMEM, anything else is assumed to be loaded to its ROM address
+        .unwrap_or(if start < 0x1000 {
+            0xA4000000 + start
+        } else {
+            start
+        });
+
+    let code = rom.slice(start, 4 * count as usize)?;
+
+    for (i, (address, word, text)) in bunny_oxide::disassemble(code, vram).into_iter().enumerate() {
+        println!(
+            "/* {:06X} {address:08X} {word:08X} */  {text}",
+            start as usize + 4 * i
+        );
+    }
+    Ok(Vec::new())
+}
+
+fn has_extension(file_name: &Path, extensions: &[&str]) -> bool {
+    file_name
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+/// Whether a file found in a directory is a ROM. Plenty of other MIPS binaries pass the
+/// byte order heuristic, so only ROMs named as such may skip the PI magic.
+fn looks_like_rom(file_name: &Path) -> bool {
+    let needed = if has_extension(file_name, &["z64", "v64", "n64"]) {
+        Confidence::High
+    } else {
+        Confidence::Exact
+    };
+    File::open(file_name)
+        .and_then(|mut file| detect::read_detect_prefix(&mut file))
+        .is_ok_and(|prefix| {
+            detect::detect_endian(&prefix).is_ok_and(|guess| guess.confidence >= needed)
+        })
+}
+
+fn looks_like_disk(file_name: &Path) -> bool {
+    has_extension(file_name, &["ndd", "d64"])
+}
+
+fn collect_roms(paths: &[PathBuf]) -> Vec<PathBuf> {
+    collect_files(paths, looks_like_rom)
+}
+
+/// Expand any directories in `paths` into the files they contain that `keep` accepts, recursively
+/// and in sorted order. Files given explicitly are kept whatever they contain, so failures get
+/// reported.
+fn collect_files(paths: &[PathBuf], keep: fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            roms.extend(
+                WalkDir::new(path)
+                    .sort_by_file_name()
+                    .into_iter()
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().is_file())
+                    .map(|entry| entry.into_path())
+                    .filter(|file_name| keep(file_name)),
+            );
+        } else {
+            roms.push(path.clone());
+        }
+    }
+    roms
+}
+
+/// Exit code when every ROM was processed without issues
+const EXIT_OK: u8 = 0;
+/// Exit code when every ROM was processed but some had warnings
+const EXIT_WARNINGS: u8 = 1;
+/// Exit code when the arguments cannot be used, the same as clap's own usage errors
+const EXIT_USAGE: u8 = 2;
+/// Exit code when at least one ROM could not be processed
+const EXIT_FAILURES: u8 = 3;
+
+/// Outcome of processing a single ROM
+enum RomStatus {
+    Ok,
+    Warning(Vec<String>),
+    Failed(Error),
 }
 
-fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
+/// Run `f`, turning a panic into an error so one bad ROM cannot abort a whole batch
+fn catch_panic<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(Error::Panicked(message))
+    })
+}
 
-    if args.len() < 2 {
-        println!("USAGE: {} ROMFILE", &args[0]);
-        return Err("Not enough arguments".to_string());
+/// Run `analyse` on every ROM in parallel, then `report` each result in order, carrying on
+/// past failures and panics, and finally print a summary. `report` returns any warnings for
+/// the ROM.
+fn each_rom<T: Send>(
+    roms: &[PathBuf],
+    analyse: impl Fn(&Path) -> Result<T> + Sync,
+    mut report: impl FnMut(&Path, Result<T>) -> Result<Vec<String>>,
+) -> ExitCode {
+    let results: Vec<_> = roms
+        .par_iter()
+        .map(|file_name| catch_panic(|| analyse(file_name)))
+        .collect();
+
+    let statuses: Vec<_> = roms
+        .iter()
+        .zip(results)
+        .map(|(file_name, result)| {
+            let status = match catch_panic(|| report(file_name, result)) {
+                Ok(warnings) if warnings.is_empty() => RomStatus::Ok,
+                Ok(warnings) => RomStatus::Warning(warnings),
+                Err(err) => RomStatus::Failed(err),
+            };
+            match &status {
+                RomStatus::Ok => log::info!("{}: ok", file_name.display()),
+                RomStatus::Warning(warnings) => {
+                    for warning in warnings {
+                        log::warn!("{}: {warning}", file_name.display());
+                    }
+                }
+                RomStatus::Failed(err) => log::error!("{}: {err}", file_name.display()),
+            }
+            status
+        })
+        .collect();
+
+    let ok = statuses
+        .iter()
+        .filter(|s| matches!(s, RomStatus::Ok))
+        .count();
+    let warned = statuses
+        .iter()
+        .filter(|s| matches!(s, RomStatus::Warning(_)))
+        .count();
+    let failed = statuses
+        .iter()
+        .filter(|s| matches!(s, RomStatus::Failed(_)))
+        .count();
+
+    if roms.len() > 1 {
+        log::info!(
+            "{} ROMs: {ok} ok, {warned} with warnings, {failed} failed",
+            roms.len()
+        );
     }
 
-    // let file_name = &args[1];
+    ExitCode::from(if failed > 0 {
+        EXIT_FAILURES
+    } else if warned > 0 {
+        EXIT_WARNINGS
+    } else {
+        EXIT_OK
+    })
+}
 
-    // run(file_name);
+/// Log to stderr at a level set by the number of `-v`s and `-q`s, overridden per module by
+/// `RUST_LOG` and then `--log`
+fn init_logging(verbose: u8, quiet: u8, filters: Option<&str>) {
+    let level = match verbose as i16 - quiet as i16 {
+        i16::MIN..=-2 => log::LevelFilter::Error,
+        -1 => log::LevelFilter::Warn,
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
 
-    let mut i = 1;
-    while i < args.len() {
-        eprintln!("{}", args[i]);
-        run(&args[i])?;
-        io::stdout().flush().unwrap();
-        i += 1;
+    let mut builder = env_logger::Builder::new();
+    builder
+        .filter_level(level)
+        .format(|buf, record| match record.level() {
+            log::Level::Error => writeln!(buf, "error: {}", record.args()),
+            log::Level::Warn => writeln!(buf, "warning: {}", record.args()),
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            _ => writeln!(buf, "[{}] {}", record.target(), record.args()),
+        })
+        .parse_default_env();
+    if let Some(filters) = filters {
+        builder.parse_filters(filters);
+    }
+    builder.init();
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    init_logging(cli.verbose, cli.quiet, cli.log.as_deref());
+    // Panics are reported as failed ROMs, so only show where they happened when asked
+    panic::set_hook(Box::new(|info| log::debug!("{info}")));
+
+    match cli.command {
+        Command::Info {
+            format,
+            fields,
+            metadata,
+            roms,
+        } => {
+            let columns = match fields.as_deref().map(csv::select_columns) {
+                Some(Ok(columns)) => columns,
+                Some(Err(err)) => {
+                    log::error!("{err}");
+                    return ExitCode::from(EXIT_USAGE);
+                }
+                None => csv::COLUMNS.iter().collect(),
+            };
+            let db = match metadata.as_deref().map(MetadataDb::load) {
+                Some(Ok(db)) => Some(db),
+                Some(Err(err)) => {
+                    log::error!("{err}");
+                    return ExitCode::from(EXIT_USAGE);
+                }
+                None => None,
+            };
+            let mut csv_writer = CsvWriter::new(io::stdout(), columns);
+            each_rom(
+                &collect_roms(&roms),
+                |file_name| {
+                    let mut info = bunny_oxide::analyze(&Rom::open(file_name)?)?;
+                    info.metadata = db.as_ref().and_then(|db| db.lookup(&info.header)).cloned();
+                    Ok(info)
+                },
+                |file_name, result| {
+                    let result = result
+                        .and_then(|info| report_info(file_name, info, format, &mut csv_writer));
+                    if let (Err(err), OutputFormat::Json) = (&result, format) {
+                        print_failure_json(&base_name(file_name), err)?;
+                    }
+                    io::stdout().flush()?;
+                    result
+                },
+            )
+        }
+        Command::Header {
+            action:
+                Some(HeaderAction::Set {
+                    image_name,
+                    cartridge_id,
+                    country_code,
+                    version,
+                    media_format,
+                    save_type,
+                    rtc,
+                    region_free,
+                    force,
+                    output,
+                    rom,
+                }),
+            ..
+        } => each_rom(
+            &[rom],
+            |_| Ok(()),
+            |file_name, _| {
+                let mut rom = open_for_writing(file_name, force)?;
+                let mut header = rom.header().clone();
+                if let Some(image_name) = &image_name {
+                    header.set_image_name(image_name)?;
+                }
+                if let Some(cartridge_id) = &cartridge_id {
+                    header.set_cartridge_id(cartridge_id)?;
+                }
+                if let Some(country_code) = country_code {
+                    header.set_country_code(country_code)?;
+                }
+                if let Some(version) = version {
+                    header.set_version(version);
+                }
+                if let Some(media_format) = media_format {
+                    header.set_media_format(media_format)?;
+                }
+                if save_type.is_some() || rtc.is_some() || region_free.is_some() {
+                    let mut flags = header.homebrew().unwrap_or(HomebrewFlags::from_byte(0));
+                    flags.save_type = save_type.unwrap_or(flags.save_type);
+                    flags.rtc = rtc.unwrap_or(flags.rtc);
+                    flags.region_free = region_free.unwrap_or(flags.region_free);
+                    header.set_homebrew(flags);
+                }
+                rom.set_header(header);
+                rom.save(output.as_deref().unwrap_or(file_name))?;
+                Ok(Vec::new())
+            },
+        ),
+        Command::Header {
+            action: None,
+            format,
+            roms,
+        } => each_rom(&collect_roms(&roms), read_header, |file_name, header| {
+            report_header(file_name, header?, format)
+        }),
+        Command::Lint { format, roms } => each_rom(
+            &collect_roms(&roms),
+            |file_name| Ok(read_header(file_name)?.lint()),
+            |file_name, findings| report_lint(file_name, findings?, format),
+        ),
+        Command::Cic { format, roms } => {
+            each_rom(&collect_roms(&roms), identify_cic, |file_name, report| {
+                report_cic(file_name, report?, format)
+            })
+        }
+        Command::Checksum { format, roms } => {
+            each_rom(&collect_roms(&roms), check_checksum, |file_name, check| {
+                report_checksum(file_name, check?, format)
+            })
+        }
+        Command::FixChecksum { force, output, rom } => each_rom(
+            &[rom],
+            |_| Ok(()),
+            |file_name, _| fix_checksum(file_name, output.as_deref().unwrap_or(file_name), force),
+        ),
+        Command::Convert {
+            to,
+            force,
+            output,
+            rom,
+        } => each_rom(
+            &[rom],
+            |_| Ok(()),
+            |file_name, _| {
+                let output = output.as_deref().unwrap_or(file_name);
+                let show_progress = cli.quiet == 0 && io::stderr().is_terminal();
+                convert_rom(file_name, output, to.into(), force, show_progress)
+            },
+        ),
+        Command::Trim { force, output, rom } => each_rom(
+            &[rom],
+            |_| Ok(()),
+            |file_name, _| {
+                let output = output.as_deref().unwrap_or(file_name);
+                resize_rom(file_name, output, true, None, force)
+            },
+        ),
+        Command::Pad {
+            fill,
+            force,
+            output,
+            rom,
+        } => each_rom(
+            &[rom],
+            |_| Ok(()),
+            |file_name, _| {
+                let output = output.as_deref().unwrap_or(file_name);
+                resize_rom(file_name, output, false, fill, force)
+            },
+        ),
+        Command::Patch {
+            action:
+                PatchAction::Apply {
+                    fix_checksum,
+                    force,
+                    output,
+                    rom,
+                    patch,
+                },
+        } => each_rom(
+            &[rom],
+            |_| Ok(()),
+            |file_name, _| {
+                let output = output.as_deref().unwrap_or(file_name);
+                apply_patch(file_name, &patch, output, fix_checksum, force)
+            },
+        ),
+        Command::Patch {
+            action:
+                PatchAction::Create {
+                    format,
+                    output,
+                    original,
+                    modified,
+                },
+        } => each_rom(
+            &[modified],
+            |_| Ok(()),
+            |file_name, _| create_patch(&original, file_name, &output, format.into()),
+        ),
+        Command::Entry { format, roms } => each_rom(
+            &collect_roms(&roms),
+            parse_entrypoint,
+            |file_name, entry| report_entry(file_name, entry?, format),
+        ),
+        Command::Disk { format, disks } => each_rom(
+            &collect_files(&disks, looks_like_disk),
+            |file_name| disk::analyze(&DiskImage::open(file_name)?),
+            |file_name, info| report_disk(file_name, info?, format),
+        ),
+        Command::Disasm {
+            start,
+            count,
+            vram,
+            rom,
+        } => each_rom(
+            &[rom],
+            |_| Ok(()),
+            |file_name, _| run_disasm(file_name, start, count, vram),
+        ),
     }
-    Ok(())
 }
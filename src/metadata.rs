@@ -0,0 +1,279 @@
+//! Game metadata from a mupen64plus.ini-style database, matched to ROMs by header checksum
+//!
+//! Each section is one ROM, named by its MD5 in mupen64plus.ini. The keys we use are
+//! `GoodName`, `CRC` (the two header checksum words), `SaveType`, `Players`, `Rumble`,
+//! `Transferpak` and `Mempak`, plus `CartridgeID` (the two-character ID then the country
+//! code, e.g. `SME`) as a fallback for databases without CRCs.
+//! `RefMD5` copies any missing keys from the named section, as mupen64plus does.
+//!
+//! Bad and overdumped copies of a ROM often keep its header, so several sections can share a CRC
+//! or cartridge ID. The first one wins, unless a later one is a verified `[!]` dump and it is not.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::n64header::N64Header;
+use crate::{Error, Result};
+
+/// What the database knows about one ROM
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct GameMetadata {
+    pub good_name: Option<String>,
+    pub save_type: Option<String>,
+    pub players: Option<u8>,
+    pub rumble: Option<bool>,
+    pub transfer_pak: Option<bool>,
+    pub controller_pak: Option<bool>,
+}
+
+impl GameMetadata {
+    /// Whether GoodName marks this as a verified good dump
+    fn is_verified(&self) -> bool {
+        self.good_name
+            .as_deref()
+            .is_some_and(|name| name.contains("[!]"))
+    }
+
+    /// Take any fields this entry is missing from `other`
+    fn inherit(&mut self, other: &GameMetadata) {
+        self.good_name = self.good_name.take().or_else(|| other.good_name.clone());
+        self.save_type = self.save_type.take().or_else(|| other.save_type.clone());
+        self.players = self.players.or(other.players);
+        self.rumble = self.rumble.or(other.rumble);
+        self.transfer_pak = self.transfer_pak.or(other.transfer_pak);
+        self.controller_pak = self.controller_pak.or(other.controller_pak);
+    }
+}
+
+/// One `[section]` of the file before references are resolved
+#[derive(Default)]
+struct Section {
+    metadata: GameMetadata,
+    crc: Option<(u32, u32)>,
+    cartridge_id: Option<String>,
+    ref_md5: Option<String>,
+}
+
+/// Metadata for every ROM in a database, indexed for lookup by header
+#[derive(Default)]
+pub struct MetadataDb {
+    by_crc: HashMap<(u32, u32), GameMetadata>,
+    by_cartridge_id: HashMap<String, GameMetadata>,
+}
+
+/// Add `metadata` under `key`, deciding between sections that share it as described above
+fn insert<K: std::hash::Hash + Eq>(
+    map: &mut HashMap<K, GameMetadata>,
+    key: K,
+    metadata: GameMetadata,
+) {
+    match map.entry(key) {
+        Entry::Vacant(entry) => {
+            entry.insert(metadata);
+        }
+        Entry::Occupied(mut entry) => {
+            if metadata.is_verified() && !entry.get().is_verified() {
+                entry.insert(metadata);
+            }
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" | "true" | "1" => Some(true),
+        "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_crc(value: &str) -> Option<(u32, u32)> {
+    let (crc1, crc2) = value.trim().split_once(' ')?;
+    Some((
+        u32::from_str_radix(crc1, 16).ok()?,
+        u32::from_str_radix(crc2.trim(), 16).ok()?,
+    ))
+}
+
+impl MetadataDb {
+    pub fn parse(text: &str) -> Result<MetadataDb> {
+        let mut sections: Vec<(String, Section)> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let malformed =
+                |reason: &str| Error::MalformedMetadata(line_number, reason.to_string());
+            let line = line.trim();
+            if line.is_empty() || line.starts_with([';', '#']) {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| malformed("unterminated section name"))?;
+                sections.push((name.to_ascii_uppercase(), Section::default()));
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| malformed("expected key=value"))?;
+            let (key, value) = (key.trim(), value.trim());
+            let (_, section) = sections
+                .last_mut()
+                .ok_or_else(|| malformed("key outside a section"))?;
+            let entry = &mut section.metadata;
+            match key {
+                "GoodName" => entry.good_name = Some(value.to_string()),
+                "SaveType" => entry.save_type = Some(value.to_string()),
+                "Players" => {
+                    entry.players = Some(
+                        value
+                            .parse()
+                            .map_err(|_| malformed("Players is not a number"))?,
+                    )
+                }
+                "Rumble" => entry.rumble = parse_bool(value),
+                "Transferpak" => entry.transfer_pak = parse_bool(value),
+                "Mempak" => entry.controller_pak = parse_bool(value),
+                "CRC" => {
+                    section.crc = Some(
+                        parse_crc(value).ok_or_else(|| malformed("CRC is not two hex words"))?,
+                    )
+                }
+                "CartridgeID" => section.cartridge_id = Some(value.to_string()),
+                "RefMD5" => section.ref_md5 = Some(value.to_ascii_uppercase()),
+                // Emulator settings we have no use for
+                _ => (),
+            }
+        }
+
+        let by_md5: HashMap<_, _> = sections
+            .iter()
+            .map(|(md5, section)| (md5.as_str(), section.metadata.clone()))
+            .collect();
+        let mut db = MetadataDb::default();
+        for (_, section) in &sections {
+            let mut metadata = section.metadata.clone();
+            if let Some(referenced) = section.ref_md5.as_deref().and_then(|md5| by_md5.get(md5)) {
+                metadata.inherit(referenced);
+            }
+            if let Some(crc) = section.crc {
+                insert(&mut db.by_crc, crc, metadata.clone());
+            }
+            if let Some(cartridge_id) = &section.cartridge_id {
+                insert(&mut db.by_cartridge_id, cartridge_id.clone(), metadata);
+            }
+        }
+        Ok(db)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<MetadataDb> {
+        MetadataDb::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Entry for a ROM, by header checksum or failing that by cartridge ID and country code
+    pub fn lookup(&self, header: &N64Header) -> Option<&GameMetadata> {
+        self.by_crc.get(&header.checksum()).or_else(|| {
+            let cartridge_id = format!("{}{}", header.cartridge_id(), header.country_code());
+            self.by_cartridge_id.get(&cartridge_id)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::n64header::read_header;
+
+    const DB: &str = "\
+; comment
+[0B1C2E8F63A25DE5B8DA16AE41E27B82]
+GoodName=Test ROM (U) [!]
+CRC=12345678 9ABCDEF0
+SaveType=Eeprom 4KB
+Players=2
+Rumble=Yes
+
+[AAAA]
+GoodName=Test ROM (U) [b1]
+CRC=30541989  0000ABCD
+RefMD5=0b1c2e8f63a25de5b8da16ae41e27b82
+Transferpak=No
+
+[BBBB]
+GoodName=Other ROM (E)
+CartridgeID=NOP
+";
+
+    fn header(checksum: (u32, u32), cartridge_id: &[u8; 3]) -> N64Header {
+        let mut data = [0; 0x40];
+        data[0x10..0x14].copy_from_slice(&checksum.0.to_be_bytes());
+        data[0x14..0x18].copy_from_slice(&checksum.1.to_be_bytes());
+        data[0x3C..0x3F].copy_from_slice(cartridge_id);
+        read_header(&data[..]).unwrap()
+    }
+
+    #[test]
+    fn lookup_by_crc() {
+        let db = MetadataDb::parse(DB).unwrap();
+        let entry = db
+            .lookup(&header((0x12345678, 0x9ABCDEF0), b"XXE"))
+            .unwrap();
+        assert_eq!(entry.good_name.as_deref(), Some("Test ROM (U) [!]"));
+        assert_eq!(entry.save_type.as_deref(), Some("Eeprom 4KB"));
+        assert_eq!(entry.players, Some(2));
+        assert_eq!(entry.rumble, Some(true));
+        assert_eq!(entry.transfer_pak, None);
+        assert!(db.lookup(&header((0x12345678, 0), b"XXE")).is_none());
+    }
+
+    #[test]
+    fn ref_md5_fills_missing_keys() {
+        let db = MetadataDb::parse(DB).unwrap();
+        let entry = db.lookup(&header((0x30541989, 0xABCD), b"XXE")).unwrap();
+        assert_eq!(entry.good_name.as_deref(), Some("Test ROM (U) [b1]"));
+        assert_eq!(entry.save_type.as_deref(), Some("Eeprom 4KB"));
+        assert_eq!(entry.players, Some(2));
+        assert_eq!(entry.transfer_pak, Some(false));
+    }
+
+    #[test]
+    fn cartridge_id_fallback() {
+        let db = MetadataDb::parse(DB).unwrap();
+        let entry = db.lookup(&header((1, 2), b"NOP")).unwrap();
+        assert_eq!(entry.good_name.as_deref(), Some("Other ROM (E)"));
+        assert!(db.lookup(&header((1, 2), b"NOE")).is_none());
+    }
+
+    #[test]
+    fn shared_crc_prefers_verified_dump() {
+        let db = MetadataDb::parse(
+            "[A]\nGoodName=Game (U) [b1]\nCRC=1 2\n\
+             [B]\nGoodName=Game (U) [!]\nCRC=1 2\n\
+             [C]\nGoodName=Game (U) [o1]\nCRC=1 2\n",
+        )
+        .unwrap();
+        let entry = db.lookup(&header((1, 2), b"XXE")).unwrap();
+        assert_eq!(entry.good_name.as_deref(), Some("Game (U) [!]"));
+    }
+
+    #[test]
+    fn malformed_lines() {
+        for (text, line) in [
+            ("GoodName=Orphan", 1),
+            ("[A\nGoodName=X", 1),
+            ("[A]\nGoodName", 2),
+            ("[A]\n\nCRC=12345678", 3),
+            ("[A]\nCRC=XYZ 0", 2),
+            ("[A]\nPlayers=two", 2),
+        ] {
+            assert!(
+                matches!(MetadataDb::parse(text), Err(Error::MalformedMetadata(at, _)) if at == line),
+                "{text:?}"
+            );
+        }
+    }
+}
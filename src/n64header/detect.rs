@@ -0,0 +1,209 @@
+//! Byte order detection, falling back to scoring each order when the PI magic is non-standard
+
+use std::io::{self, Read};
+
+use super::{Endian, STANDARD_PI_BSD_DOM1};
+use crate::error::{self, Error};
+use crate::reend_array;
+
+/// Bytes of a ROM looked at to guess its byte order: the header, IPL3 and the start of the
+/// boot segment
+pub const DETECT_LEN: usize = 0x1400;
+
+/// Fewer non-zero words than this in the code says nothing about the byte order
+const MIN_CODE_WORDS: usize = 16;
+
+/// How sure we are of a detected byte order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+    /// The standard PI magic was found
+    Exact,
+}
+
+impl std::fmt::Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Confidence::Low => "low",
+                Confidence::Medium => "medium",
+                Confidence::High => "high",
+                Confidence::Exact => "exact",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct EndianGuess {
+    pub endian: Endian,
+    pub confidence: Confidence,
+}
+
+impl EndianGuess {
+    /// The guessed byte order, if it is at least `needed` confidence
+    pub fn require(&self, needed: Confidence) -> error::Result<Endian> {
+        if self.confidence >= needed {
+            Ok(self.endian)
+        } else {
+            Err(Error::UncertainByteOrder(self.confidence))
+        }
+    }
+}
+
+/// How plausible the start of a ROM looks when read in byte order `endian`: up to 3 points for
+/// the header and 2 for the fraction of valid instructions in the code
+fn score(input: &[u8], endian: Endian) -> f32 {
+    let mut view = input[..input.len().min(DETECT_LEN) & !3].to_vec();
+    reend_array(&mut view, &endian);
+    let word = |offset: usize| u32::from_be_bytes(view[offset..offset + 4].try_into().unwrap());
+
+    let mut header = 0.0;
+    // Every PI timing word in use starts with the same byte
+    if view[0] == STANDARD_PI_BSD_DOM1[0] {
+        header += 1.0;
+    }
+    if (0x80000000..0x80800000).contains(&word(0x08)) {
+        header += 1.0;
+    }
+    // The media format is a single ASCII letter in the low byte of its word
+    let media_format = word(0x38);
+    if media_format < 0x100 && (media_format as u8).is_ascii_uppercase() {
+        header += 1.0;
+    }
+
+    let code: Vec<u32> = view[0x40..]
+        .chunks_exact(4)
+        .map(|chunk| u32::from_be_bytes(chunk.try_into().unwrap()))
+        .filter(|&word| word != 0)
+        .collect();
+    let code_score = if code.len() < MIN_CODE_WORDS {
+        0.0
+    } else {
+        let valid = code
+            .iter()
+            .filter(|&&word| rabbitizer::Instruction::new(word, 0).is_valid())
+            .count();
+        2.0 * valid as f32 / code.len() as f32
+    };
+
+    log::debug!(
+        "{}: header {header}, code {code_score:.2}",
+        endian.extension()
+    );
+    header + code_score
+}
+
+/// Work out the byte order of a ROM from its first bytes, ideally `DETECT_LEN` of them
+pub fn detect_endian(input: &[u8]) -> error::Result<EndianGuess> {
+    let magic: [u8; 4] = input
+        .get(0..4)
+        .ok_or(Error::TruncatedRom { offset: 0, len: 4 })?
+        .try_into()
+        .unwrap();
+    let exact = |endian| {
+        Ok(EndianGuess {
+            endian,
+            confidence: Confidence::Exact,
+        })
+    };
+    match magic {
+        [0x80, 0x37, 0x12, 0x40] => return exact(Endian::Good),
+        [0x40, 0x12, 0x37, 0x80] => return exact(Endian::Bad),
+        [0x37, 0x80, 0x40, 0x12] => return exact(Endian::Ugly),
+        _ if input.len() < 0x40 => return Err(Error::UnknownByteOrder(magic)),
+        _ => (),
+    }
+
+    let mut scores: Vec<_> = [Endian::Good, Endian::Bad, Endian::Ugly]
+        .into_iter()
+        .map(|endian| (endian, score(input, endian)))
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let (endian, best) = scores[0];
+    let margin = best - scores[1].1;
+
+    // Nothing looks like a ROM, or two orders look equally like one
+    if best < 2.0 || margin <= 0.0 {
+        return Err(Error::UnknownByteOrder(magic));
+    }
+    let confidence = if margin >= 1.5 {
+        Confidence::High
+    } else if margin >= 0.75 {
+        Confidence::Medium
+    } else {
+        Confidence::Low
+    };
+    Ok(EndianGuess { endian, confidence })
+}
+
+/// Read up to `DETECT_LEN` bytes from the start of a ROM, for `detect_endian`
+pub fn read_detect_prefix(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut prefix = Vec::with_capacity(DETECT_LEN);
+    reader.take(DETECT_LEN as u64).read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{stored, z64};
+
+    #[test]
+    fn exact_magic() {
+        for endian in [Endian::Good, Endian::Bad, Endian::Ugly] {
+            let guess = detect_endian(&stored(z64(0x80371240), endian)[..4]).unwrap();
+            assert_eq!(guess.endian, endian);
+            assert_eq!(guess.confidence, Confidence::Exact);
+        }
+    }
+
+    #[test]
+    fn non_standard_magic() {
+        for endian in [Endian::Good, Endian::Bad, Endian::Ugly] {
+            let guess = detect_endian(&stored(z64(0x80270740), endian)).unwrap();
+            assert_eq!(guess.endian, endian);
+            assert_eq!(guess.confidence, Confidence::High);
+        }
+    }
+
+    #[test]
+    fn writing_needs_a_sure_guess() {
+        // With no code to go on, the first byte and the entrypoint also fit when byte-swapped
+        let mut rom = z64(0x80371280);
+        rom[0x08..0x0C].copy_from_slice(&0x80000080u32.to_be_bytes());
+        rom[0x40..].fill(0);
+        let guess = detect_endian(&rom).unwrap();
+        assert_eq!(guess.endian, Endian::Good);
+        assert!(guess.confidence < Confidence::High);
+        assert!(matches!(
+            guess.require(Confidence::High),
+            Err(Error::UncertainByteOrder(_))
+        ));
+        assert_eq!(guess.require(Confidence::Low).unwrap(), Endian::Good);
+
+        let guess = detect_endian(&z64(0x80270740)).unwrap();
+        assert_eq!(guess.require(Confidence::High).unwrap(), Endian::Good);
+    }
+
+    #[test]
+    fn not_a_rom() {
+        assert!(matches!(
+            detect_endian(&[0; DETECT_LEN]),
+            Err(Error::UnknownByteOrder([0, 0, 0, 0]))
+        ));
+        assert!(matches!(
+            detect_endian(&[0x12, 0x34, 0x56, 0x78, 0]),
+            Err(Error::UnknownByteOrder(_))
+        ));
+        assert!(matches!(
+            detect_endian(&[0x80]),
+            Err(Error::TruncatedRom { .. })
+        ));
+    }
+}
@@ -1,30 +1,22 @@
-use super::super::VERBOSE;
+use crate::error::{self, Error};
 use crate::mips::*;
-use crate::n64header::Endian;
 use ::rabbitizer;
 use enum_map::EnumMap;
+use serde::Serialize;
 
 use super::super::MyInstruction;
 
-fn bytes_to_reend_word(bytes: &[u8], endian: &Endian) -> u32 {
-    assert!(bytes.len() >= 4);
-    match endian {
-        Endian::Good => u32::from_be_bytes(bytes.try_into().unwrap()),
-        Endian::Bad => u32::from_le_bytes(bytes.try_into().unwrap()),
-        Endian::Ugly => u32::from_be_bytes([bytes[1], bytes[0], bytes[3], bytes[2]]),
-    }
-}
-
 fn add_signed_imm(u: u32, s: i32) -> u32 {
     if s >= 0 {
-        u + s as u32
+        u.wrapping_add(s as u32)
     } else {
-        u - (-s as u32)
+        u.wrapping_sub(s.unsigned_abs())
     }
 }
-#[derive(Debug)]
+/// Instruction used to build the lower half of an address after a `lui`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 #[allow(non_camel_case_types)]
-enum LowerAddrOp {
+pub enum LowerAddrOp {
     None,
     addiu,
     ori,
@@ -36,7 +28,113 @@ impl Default for LowerAddrOp {
     }
 }
 
-pub fn parse(data: &[u8], address: u32, endian: &Endian, base_name: &str) -> (u32, u32, u32, u32) {
+/// How the entrypoint transfers control to the game's main function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JumpKind {
+    Jal,
+    Jr,
+}
+
+impl std::fmt::Display for JumpKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                JumpKind::Jal => "jal",
+                JumpKind::Jr => "jr",
+            }
+        )
+    }
+}
+
+/// Contents of the delay slot of the final jump
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DelaySlot {
+    /// Entrypoint ended without finding a jump
+    NotFound,
+    /// `nop`, suggests GCC's assembler
+    Nop,
+    /// Filled with a real instruction, suggests IDO's assembler
+    Used,
+}
+
+impl std::fmt::Display for DelaySlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DelaySlot::NotFound => "",
+                DelaySlot::Nop => "nop",
+                DelaySlot::Used => "yep",
+            }
+        )
+    }
+}
+
+/// Results of parsing the entrypoint function
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct EntrypointInfo {
+    /// Length of the entrypoint up to the final jump's delay slot, in bytes
+    pub length: usize,
+    pub initial_sp: u32,
+    pub bss_start: u32,
+    pub bss_size: u32,
+    pub jump_addr: u32,
+    /// Register holding the bss start pointer
+    pub bss_start_reg: MipsGpr,
+    /// Register holding the bss size (or end)
+    pub bss_size_reg: MipsGpr,
+    /// Register jumped through, `zero` if a `jal` was used
+    pub jump_reg: MipsGpr,
+    pub sp_op: LowerAddrOp,
+    pub bss_start_op: LowerAddrOp,
+    pub bss_size_op: LowerAddrOp,
+    pub jump_kind: JumpKind,
+    pub final_delay_slot: DelaySlot,
+    /// Whether a `break` follows shortly after the entrypoint
+    pub has_break: bool,
+}
+
+impl EntrypointInfo {
+    /// Warn if the initial stack pointer is outside the RAM that ends at `ram_end`
+    pub fn stack_warning(&self, ram_end: u32) -> Option<String> {
+        (!(0x80000000..=ram_end).contains(&self.initial_sp)).then(|| {
+            format!(
+                "Initial stack pointer {:08X} is outside RAM (80000000-{ram_end:08X})",
+                self.initial_sp
+            )
+        })
+    }
+
+    /// Describe anything unusual about the entrypoint that is worth a closer look
+    pub fn warning(&self) -> Option<String> {
+        if self.length > 0x40 {
+            Some(format!(
+                "Read entrypoint is unusually long ({:#X} bytes), recommend closer investigation",
+                self.length
+            ))
+        } else if self.length < 0x30 {
+            Some(format!(
+                "Read entrypoint is unusually short ({:#X} bytes), recommend closer investigation",
+                self.length
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// End of KSEG0 RAM on an N64 with the Expansion Pak
+pub const N64_RAM_END: u32 = 0x80800000;
+/// End of KSEG0 RAM on the iQue Player, which has 16 MiB
+pub const IQUE_RAM_END: u32 = 0x81000000;
+
+/// Parse the big-endian entrypoint function in `data`, which is loaded at `address`
+pub fn parse(data: &[u8], address: u32) -> error::Result<EntrypointInfo> {
     let mut reg_tracker: EnumMap<MipsGpr, u32> = EnumMap::default();
     let mut reg_ops: EnumMap<MipsGpr, LowerAddrOp> = EnumMap::default();
     let mut bss_ptr_reg: MipsGpr = MipsGpr::zero;
@@ -44,47 +142,47 @@ pub fn parse(data: &[u8], address: u32, endian: &Endian, base_name: &str) -> (u3
     let mut bss_size: u32 = 0;
     let mut jump_reg: MipsGpr = MipsGpr::zero;
     let mut jump_addr: Option<u32> = None;
-    let mut bss_start: u32;
     let mut jal_found = false;
-    let mut final_delay_slot_used = "";
-
-    let mut current_ram_address = address;
-    let mut current_rom_address = 0x1000;
+    let mut final_delay_slot = DelaySlot::NotFound;
 
     let mut prev_was_jump = false;
 
     let mut length = 0;
 
     for (i, chunk) in data.chunks_exact(4).enumerate() {
-        let word = bytes_to_reend_word(chunk, endian);
+        let word = u32::from_be_bytes(chunk.try_into().unwrap());
 
-        let instr = rabbitizer::Instruction::new(word, address + 4 * i as u32);
+        let instr = rabbitizer::Instruction::new(word, address.wrapping_add(4 * i as u32));
         let my_instruction = MyInstruction { instr };
 
-        // println!("{:?}", my_instruction.instr.instr_id());
+        log::trace!(
+            "{:08X}: {:?}",
+            address.wrapping_add(4 * i as u32),
+            my_instruction.instr.instr_id()
+        );
         match my_instruction.instr.instr_id() {
             rabbitizer::InstrId::RABBITIZER_INSTR_ID_cpu_lui => {
                 reg_tracker[my_instruction.instr_get_rt()] =
                     (my_instruction.instr.processed_immediate() << 16) as u32;
-                // println!("lui: {:#X}", my_instruction.instr.processed_immediate());
+                log::trace!("lui: {:#X}", my_instruction.instr.processed_immediate());
             }
             rabbitizer::InstrId::RABBITIZER_INSTR_ID_cpu_addiu => {
                 let out_reg = my_instruction.instr_get_rt();
                 // Already applied an addiu
                 if reg_tracker[out_reg] & 0xFFFF == 0 {
-                    // println!(
-                    //     "addiuing: {:#X} + {:#X}",
-                    //     reg_tracker[my_instruction.instr_get_rs()],
-                    //     my_instruction.instr.processed_immediate()
-                    // );
+                    log::trace!(
+                        "addiuing: {:#X} + {:#X}",
+                        reg_tracker[my_instruction.instr_get_rs()],
+                        my_instruction.instr.processed_immediate()
+                    );
                     reg_tracker[out_reg] = add_signed_imm(
                         reg_tracker[my_instruction.instr_get_rs()],
                         my_instruction.instr.processed_immediate(),
                     );
-                    // println!("= {:#X}", reg_tracker[out_reg]);
+                    log::trace!("= {:#X}", reg_tracker[out_reg]);
                     reg_ops[out_reg] = LowerAddrOp::addiu;
                 } else {
-                    // println!("addiu blocked: {:#X}", reg_tracker[out_reg] & 0xFFFF);
+                    log::trace!("addiu blocked: {:#X}", reg_tracker[out_reg] & 0xFFFF);
                 }
             }
             rabbitizer::InstrId::RABBITIZER_INSTR_ID_cpu_ori => {
@@ -121,15 +219,11 @@ pub fn parse(data: &[u8], address: u32, endian: &Endian, base_name: &str) -> (u3
         // Stop after the instruction after the jump
         if prev_was_jump {
             if my_instruction.instr.is_nop() {
-                if VERBOSE {
-                    println!("Final delay slot NOP. GCC assembler?");
-                }
-                final_delay_slot_used = "nop";
+                log::debug!("Final delay slot NOP. GCC assembler?");
+                final_delay_slot = DelaySlot::Nop;
             } else {
-                if VERBOSE {
-                    println!("Final delay slot used. IDO assembler?");
-                }
-                final_delay_slot_used = "yep";
+                log::debug!("Final delay slot used. IDO assembler?");
+                final_delay_slot = DelaySlot::Used;
             }
             length = 4 * i;
             break;
@@ -140,7 +234,7 @@ pub fn parse(data: &[u8], address: u32, endian: &Endian, base_name: &str) -> (u3
         }
     }
 
-    bss_start = reg_tracker[bss_ptr_reg];
+    let bss_start = reg_tracker[bss_ptr_reg];
 
     // Work out the rest of the bss stuff
     if bss_size_reg == MipsGpr::zero {
@@ -159,65 +253,44 @@ pub fn parse(data: &[u8], address: u32, endian: &Endian, base_name: &str) -> (u3
         bss_size = reg_tracker[bss_size_reg];
     }
 
-    if jump_addr.is_none() {
-        jump_addr = Some(reg_tracker[jump_reg]);
+    if final_delay_slot == DelaySlot::NotFound {
+        return Err(Error::UnparseableEntrypoint("no jump to main found"));
     }
 
-    bss_start = reg_tracker[bss_ptr_reg];
+    let jump_addr = jump_addr.unwrap_or(reg_tracker[jump_reg]);
 
-    
-    if length > 0x40 {
-        eprintln!(
-            "{base_name}: Read entrypoint is unusually long ({:#X} bytes), recommend closer investigation",
-            length
-        );
-    } else if length < 0x30 {
-        eprintln!(
-            "{base_name}: Read entrypoint is unusually short ({:#X} bytes), recommend closer investigation",
-            length
-        );
-    }
-    
     let mut has_break = false;
-    for (i, chunk) in data[0..length + 0x10].chunks_exact(4).enumerate() {
-        let word = bytes_to_reend_word(chunk, endian);
-        let instr = rabbitizer::Instruction::new(word, address + 4 * i as u32);
+    for (i, chunk) in data[0..data.len().min(length + 0x10)]
+        .chunks_exact(4)
+        .enumerate()
+    {
+        let word = u32::from_be_bytes(chunk.try_into().unwrap());
+        let instr = rabbitizer::Instruction::new(word, address.wrapping_add(4 * i as u32));
 
         if instr.instr_id() == rabbitizer::InstrId::RABBITIZER_INSTR_ID_cpu_break {
             has_break = true;
             break;
         }
     }
-    
-    if !VERBOSE {
-        print!(
-            "{:X}; {:X}; {:X}; {:X}; {:X}; {:?}; {:?}; {:?}; {}; {}; {};",
-            length,
-            reg_tracker[MipsGpr::sp],
-            bss_start,
-            bss_size,
-            jump_addr.unwrap(),
-            reg_ops[MipsGpr::sp],
-            reg_ops[bss_ptr_reg],
-            reg_ops[bss_size_reg],
-            if jal_found { "jal" } else { "jr" },
-            final_delay_slot_used,
-            has_break
-        );
-    }
-
-    // println!("jump to:    {:#010X}", jump_addr.unwrap());
-    // println!("bss start:  {:#010X}", bss_start);
-    // println!("bss size:   {:#10X}", bss_size);
-    // println!("initial sp: {:#010X}", reg_tracker[MipsGpr::sp]);
-    // for x in reg_tracker {
-    //     println!("{:?}, {:#X}", x.0, x.1);
-    // }
 
-    (
-        jump_addr.unwrap(),
+    Ok(EntrypointInfo {
+        length,
+        initial_sp: reg_tracker[MipsGpr::sp],
         bss_start,
         bss_size,
-        reg_tracker[MipsGpr::sp],
-    )
+        jump_addr,
+        bss_start_reg: bss_ptr_reg,
+        bss_size_reg,
+        jump_reg,
+        sp_op: reg_ops[MipsGpr::sp],
+        bss_start_op: reg_ops[bss_ptr_reg],
+        bss_size_op: reg_ops[bss_size_reg],
+        jump_kind: if jal_found {
+            JumpKind::Jal
+        } else {
+            JumpKind::Jr
+        },
+        final_delay_slot,
+        has_break,
+    })
 }
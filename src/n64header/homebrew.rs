@@ -0,0 +1,131 @@
+//! The Advanced Homebrew ROM Header, flagged by a cartridge ID of "ED", which repurposes
+//! the version byte to tell emulators and flashcarts how to set up the cartridge
+
+use crate::error::{self, Error};
+use std::str::FromStr;
+
+/// Cartridge ID that marks a ROM as using the advanced homebrew header
+pub const HOMEBREW_CARTRIDGE_ID: [u8; 2] = *b"ED";
+
+const RTC_FLAG: u8 = 0x01;
+const REGION_FREE_FLAG: u8 = 0x02;
+
+/// Save hardware requested in the top nibble of the version byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveType {
+    None,
+    Eeprom4k,
+    Eeprom16k,
+    Sram256k,
+    /// Three banks of 256 Kbit
+    Sram768k,
+    FlashRam1m,
+    Sram1m,
+    /// Reserved for future save types
+    Unknown(u8),
+}
+
+impl SaveType {
+    const NAMES: [(SaveType, &'static str); 7] = [
+        (SaveType::None, "none"),
+        (SaveType::Eeprom4k, "eeprom4k"),
+        (SaveType::Eeprom16k, "eeprom16k"),
+        (SaveType::Sram256k, "sram256k"),
+        (SaveType::Sram768k, "sram768k"),
+        (SaveType::FlashRam1m, "flashram1m"),
+        (SaveType::Sram1m, "sram1m"),
+    ];
+
+    const fn from_nibble(nibble: u8) -> SaveType {
+        match nibble {
+            0 => SaveType::None,
+            1 => SaveType::Eeprom4k,
+            2 => SaveType::Eeprom16k,
+            3 => SaveType::Sram256k,
+            4 => SaveType::Sram768k,
+            5 => SaveType::FlashRam1m,
+            6 => SaveType::Sram1m,
+            _ => SaveType::Unknown(nibble),
+        }
+    }
+
+    const fn to_nibble(self) -> u8 {
+        match self {
+            SaveType::None => 0,
+            SaveType::Eeprom4k => 1,
+            SaveType::Eeprom16k => 2,
+            SaveType::Sram256k => 3,
+            SaveType::Sram768k => 4,
+            SaveType::FlashRam1m => 5,
+            SaveType::Sram1m => 6,
+            SaveType::Unknown(nibble) => nibble,
+        }
+    }
+}
+
+impl std::fmt::Display for SaveType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match SaveType::NAMES
+            .iter()
+            .find(|(save_type, _)| save_type == self)
+        {
+            Some((_, name)) => write!(f, "{name}"),
+            None => write!(f, "unknown ({:X})", self.to_nibble()),
+        }
+    }
+}
+
+impl FromStr for SaveType {
+    type Err = Error;
+
+    fn from_str(name: &str) -> error::Result<SaveType> {
+        SaveType::NAMES
+            .iter()
+            .find(|(_, known)| known.eq_ignore_ascii_case(name))
+            .map(|(save_type, _)| *save_type)
+            .ok_or_else(|| {
+                let names: Vec<_> = SaveType::NAMES.iter().map(|(_, name)| *name).collect();
+                Error::InvalidHeaderField(
+                    "save_type",
+                    format!("\"{name}\" is not one of {}", names.join(", ")),
+                )
+            })
+    }
+}
+
+impl serde::Serialize for SaveType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Settings decoded from the version byte of a ROM with the advanced homebrew header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct HomebrewFlags {
+    pub save_type: SaveType,
+    /// Cartridge has a real-time clock
+    pub rtc: bool,
+    /// Boot regardless of the console's region
+    pub region_free: bool,
+}
+
+impl HomebrewFlags {
+    pub const fn from_byte(byte: u8) -> HomebrewFlags {
+        HomebrewFlags {
+            save_type: SaveType::from_nibble(byte >> 4),
+            rtc: byte & RTC_FLAG != 0,
+            region_free: byte & REGION_FREE_FLAG != 0,
+        }
+    }
+
+    pub const fn to_byte(self) -> u8 {
+        let mut byte = self.save_type.to_nibble() << 4;
+        if self.rtc {
+            byte |= RTC_FLAG;
+        }
+        if self.region_free {
+            byte |= REGION_FREE_FLAG;
+        }
+        byte
+    }
+}
@@ -1,12 +1,33 @@
+use crate::error::{self, Error};
 use crc;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::io;
 
-// #[derive(Debug, Clone)]
+/// Boot checksum offset, in ROM bytes, after the IPL3
+pub const CHECKSUM_START: usize = 0x1000;
+/// Amount of ROM covered by the boot checksum
+pub const CHECKSUM_LENGTH: usize = 0x100000;
+
+/// How a CIC's IPL3 mixes its registers into the boot checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChecksumVariant {
+    Xor,
+    /// 6103 adds rather than xors the final registers
+    Add,
+    /// 6105 mixes in words from its own IPL3
+    Ipl3,
+    /// 6106 multiplies the final registers
+    Multiply,
+}
+
+#[derive(Debug, Clone)]
 pub struct CICInfo {
     checksum: u32,
     ntsc_name: &'static str,
     pal_name: &'static str,
     entrypoint_offset: u32,
+    seed: u32,
+    variant: ChecksumVariant,
 }
 
 impl CICInfo {
@@ -15,27 +36,52 @@ impl CICInfo {
         ntsc_name: &'static str,
         pal_name: &'static str,
         entrypoint_offset: u32,
+        seed: u32,
+        variant: ChecksumVariant,
     ) -> CICInfo {
         CICInfo {
             checksum,
             ntsc_name,
             pal_name,
             entrypoint_offset,
+            seed,
+            variant,
         }
     }
 
     pub const fn get_from_crc(crc: u32) -> io::Result<CICInfo> {
+        use ChecksumVariant::*;
         Ok(match crc {
-            0xD1F2D592 => CICInfo::new(0xD1F2D592, "6102", "7101", 0x000000),
-            0x27DF61E2 => CICInfo::new(0x27DF61E2, "6103", "7103", 0x100000),
-            0x229F516C => CICInfo::new(0x229F516C, "6105", "7105", 0x000000),
-            0xA0DD69F7 => CICInfo::new(0xA0DD69F7, "6106", "7106", 0x200000),
-            0x0013579C => CICInfo::new(0x0013579C, "6101", "-", 0x000000),
-            0xDAB442CD => CICInfo::new(0xDAB442CD, "-", "7102", 0x80000480),
-            _ => CICInfo::new(0, "unk", "unk", 0x000000),
+            0xD1F2D592 => CICInfo::new(0xD1F2D592, "6102", "7101", 0x000000, 0xF8CA4DDC, Xor),
+            0x27DF61E2 => CICInfo::new(0x27DF61E2, "6103", "7103", 0x100000, 0xA3886759, Add),
+            0x229F516C => CICInfo::new(0x229F516C, "6105", "7105", 0x000000, 0xDF26F436, Ipl3),
+            0xA0DD69F7 => CICInfo::new(0xA0DD69F7, "6106", "7106", 0x200000, 0x1FEA617A, Multiply),
+            0x0013579C => CICInfo::new(0x0013579C, "6101", "-", 0x000000, 0xF8CA4DDC, Xor),
+            0xDAB442CD => CICInfo::new(0xDAB442CD, "-", "7102", 0x80000480, 0xF8CA4DDC, Xor),
+            _ => CICInfo::new(crc, "unk", "unk", 0x000000, 0, Xor),
         })
     }
 
+    /// iQue Player games are booted by the iQue's secure kernel rather than an IPL3, which
+    /// leaves the entrypoint as it is and checks a signature instead of a boot checksum
+    pub const fn ique(crc: u32) -> CICInfo {
+        CICInfo::new(crc, "iQue", "-", 0x000000, 0xF8CA4DDC, ChecksumVariant::Xor)
+    }
+
+    pub fn is_ique(&self) -> bool {
+        self.ntsc_name == "iQue"
+    }
+
+    /// Whether the IPL3 matched a known CIC
+    pub fn is_known(&self) -> bool {
+        self.ntsc_name != "unk"
+    }
+
+    /// CRC of the IPL3 this was identified from
+    pub const fn ipl3_crc(&self) -> u32 {
+        self.checksum
+    }
+
     pub fn name(&self) -> String {
         if self.ntsc_name == "-" {
             format!("{}", self.pal_name)
@@ -52,23 +98,190 @@ impl CICInfo {
     }
 
     /// Correct the entrypoint: most add a specified number, 7102 hardcodes it.
+    /// A header entrypoint too low for the offset wraps, leaving the entrypoint analysis to
+    /// report it rather than failing to open the ROM.
     pub const fn correct_entrypoint(&self, header_entrypoint: u32) -> u32 {
         let offset = self.entrypoint_offset();
         if offset >= 0x80000000 {
             offset
         } else {
-            header_entrypoint - offset
+            header_entrypoint.wrapping_sub(offset)
+        }
+    }
+
+    /// Compute the boot checksum IPL3 expects to find in the header of a big-endian ROM
+    pub fn calculate_checksum(&self, rom: &[u8]) -> error::Result<(u32, u32)> {
+        if !self.is_known() {
+            return Err(Error::UnknownCic(self.checksum));
+        }
+        if self.is_ique() {
+            return Err(Error::NoBootChecksum("iQue"));
+        }
+        let data = rom
+            .get(CHECKSUM_START..CHECKSUM_START + CHECKSUM_LENGTH)
+            .ok_or(Error::TruncatedRom {
+                offset: CHECKSUM_START as u64,
+                len: CHECKSUM_LENGTH,
+            })?;
+        let word = |bytes: &[u8]| u32::from_be_bytes(bytes.try_into().unwrap());
+
+        let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (
+            self.seed, self.seed, self.seed, self.seed, self.seed, self.seed,
+        );
+
+        for (i, chunk) in data.chunks_exact(4).enumerate() {
+            let d = word(chunk);
+            let (sum, carry) = t6.overflowing_add(d);
+            if carry {
+                t4 = t4.wrapping_add(1);
+            }
+            t6 = sum;
+            t3 ^= d;
+            let r = d.rotate_left(d & 0x1F);
+            t5 = t5.wrapping_add(r);
+            if t2 > d {
+                t2 ^= r;
+            } else {
+                t2 ^= t6 ^ d;
+            }
+            t1 = if self.variant == ChecksumVariant::Ipl3 {
+                let offset = 0x0750 + ((i * 4) & 0xFF);
+                t1.wrapping_add(word(&rom[offset..offset + 4]) ^ d)
+            } else {
+                t1.wrapping_add(t5 ^ d)
+            };
+        }
+
+        Ok(match self.variant {
+            ChecksumVariant::Add => ((t6 ^ t4).wrapping_add(t3), (t5 ^ t2).wrapping_add(t1)),
+            ChecksumVariant::Multiply => (
+                t6.wrapping_mul(t4).wrapping_add(t3),
+                t5.wrapping_mul(t2).wrapping_add(t1),
+            ),
+            ChecksumVariant::Xor | ChecksumVariant::Ipl3 => (t6 ^ t4 ^ t3, t5 ^ t2 ^ t1),
+        })
+    }
+}
+
+/// Stored boot checksum compared against the one IPL3 would compute
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct ChecksumCheck {
+    pub stored: (u32, u32),
+    pub calculated: (u32, u32),
+}
+
+impl ChecksumCheck {
+    pub fn is_ok(&self) -> bool {
+        self.stored == self.calculated
+    }
+}
+
+impl std::fmt::Display for ChecksumCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_ok() {
+            write!(f, "OK")
+        } else {
+            write!(
+                f,
+                "mismatch (expected {:08X} {:08X})",
+                self.calculated.0, self.calculated.1
+            )
         }
     }
 }
 
-pub fn identify(mut reader: impl io::Read) -> io::Result<CICInfo> {
-    let mut ipl3 = [0u8; 0x1000 - 0x40];
-    reader.read_exact(&mut ipl3)?;
+impl Serialize for CICInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CICInfo", 2)?;
+        state.serialize_field("name", &self.name())?;
+        state.serialize_field("ipl3_crc", &self.checksum)?;
+        state.end()
+    }
+}
+
+/// Identify the CIC from the IPL3 in the first 0x1000 bytes of a big-endian ROM
+pub fn identify(rom: &[u8]) -> error::Result<CICInfo> {
+    let ipl3 = rom.get(0x40..0x1000).ok_or(Error::TruncatedRom {
+        offset: 0x40,
+        len: 0x1000 - 0x40,
+    })?;
 
     const CRC_ALG: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
 
-    let hash = CRC_ALG.checksum(&ipl3);
+    let hash = CRC_ALG.checksum(ipl3);
+    let cic_info = CICInfo::get_from_crc(hash)?;
 
-    CICInfo::get_from_crc(hash)
+    // Decrypted iQue images have no IPL3 at all, unlike homebrew with a blank country code
+    if !cic_info.is_known()
+        && ipl3.iter().all(|&byte| byte == 0)
+        && super::read_header(&rom[..0x40])?.has_ique_country_code()
+    {
+        return Ok(CICInfo::ique(hash));
+    }
+    Ok(cic_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{cart, noise};
+
+    /// Checksums of `noise(0x101000, 0x6102)` from the n64crc reference implementation
+    #[test]
+    fn checksum_matches_reference() {
+        let rom = noise(CHECKSUM_START + CHECKSUM_LENGTH, 0x6102);
+        for (ipl3_crc, expected) in [
+            (0x0013579C, (0xC156C27F, 0xBFA4C9D9)),
+            (0xD1F2D592, (0xC156C27F, 0xBFA4C9D9)),
+            (0xDAB442CD, (0xC156C27F, 0xBFA4C9D9)),
+            (0x27DF61E2, (0xE42CE0F6, 0xFDFA0502)),
+            (0x229F516C, (0xDF3BABC9, 0xE77953B1)),
+            (0xA0DD69F7, (0xB10BC09A, 0x4805365F)),
+        ] {
+            let cic = CICInfo::get_from_crc(ipl3_crc).unwrap();
+            assert_eq!(
+                cic.calculate_checksum(&rom).unwrap(),
+                expected,
+                "{}",
+                cic.name()
+            );
+        }
+    }
+
+    #[test]
+    fn checksum_needs_known_cic_and_whole_range() {
+        let rom = noise(CHECKSUM_START + CHECKSUM_LENGTH, 0x6102);
+        let unknown = CICInfo::get_from_crc(0x12345678).unwrap();
+        assert!(matches!(
+            unknown.calculate_checksum(&rom),
+            Err(Error::UnknownCic(0x12345678))
+        ));
+        let cic = CICInfo::get_from_crc(0xD1F2D592).unwrap();
+        assert!(matches!(
+            cic.calculate_checksum(&rom[..rom.len() - 4]),
+            Err(Error::TruncatedRom { .. })
+        ));
+    }
+
+    #[test]
+    fn low_entrypoint_wraps() {
+        let cic = CICInfo::get_from_crc(0x27DF61E2).unwrap();
+        assert_eq!(cic.correct_entrypoint(0x80100400), 0x80000400);
+        assert_eq!(cic.correct_entrypoint(0x400), 0xFFF00400);
+    }
+
+    #[test]
+    fn ique_needs_blank_ipl3() {
+        let ique = identify(&cart(b'C', 0)).unwrap();
+        assert!(ique.is_ique());
+        assert!(matches!(
+            ique.calculate_checksum(&cart(b'C', 0)),
+            Err(Error::NoBootChecksum(_))
+        ));
+
+        // Homebrew with its own IPL3 and no country code
+        let homebrew = identify(&cart(0, 0x5A)).unwrap();
+        assert!(!homebrew.is_known());
+        assert!(!homebrew.is_ique());
+    }
 }
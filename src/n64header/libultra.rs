@@ -0,0 +1,177 @@
+//! The libultra release recorded in the header's revision word by the SDK's makerom
+
+use crate::compiler::{Compiler, CompilerGuess};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/// First and last revision letters of the 2.0 releases
+const FIRST_REVISION: char = 'D';
+const LAST_REVISION: char = 'L';
+/// The release the iQue Player's libultra was built from
+const IQUE_REVISION: char = 'L';
+
+/// The revision word split into its parts: two reserved bytes, the release as
+/// major * 10 + minor (0x14 for 2.0), and a letter for the revision
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LibultraVersion {
+    reserved: u16,
+    release: u8,
+    revision: u8,
+    /// iQue Player ROMs carry a build of 2.0L modified for the iQue's OS. Only the CIC can tell
+    /// an iQue image apart, so this is `None` when just the header was read.
+    ique: Option<bool>,
+}
+
+impl LibultraVersion {
+    pub const fn new(revision_word: u32, ique: Option<bool>) -> LibultraVersion {
+        LibultraVersion {
+            reserved: (revision_word >> 16) as u16,
+            release: (revision_word >> 8) as u8,
+            revision: revision_word as u8,
+            ique,
+        }
+    }
+
+    /// Upper two bytes of the revision word, zero on retail ROMs
+    pub const fn reserved(&self) -> u16 {
+        self.reserved
+    }
+
+    pub const fn major(&self) -> u8 {
+        self.release / 10
+    }
+
+    pub const fn minor(&self) -> u8 {
+        self.release % 10
+    }
+
+    pub fn revision(&self) -> Option<char> {
+        Some(char::from(self.revision)).filter(char::is_ascii_uppercase)
+    }
+
+    /// Whether the release is the iQue build, if the CIC was known when this was decoded
+    pub const fn is_ique(&self) -> Option<bool> {
+        self.ique
+    }
+
+    /// Whether this is one of the releases that shipped in an SDK, 2.0D to 2.0L
+    pub fn is_known(&self) -> bool {
+        self.reserved == 0
+            && self.release == 20
+            && self
+                .revision()
+                .is_some_and(|revision| (FIRST_REVISION..=LAST_REVISION).contains(&revision))
+    }
+
+    /// Name of the release, e.g. "2.0K" or "2.0L (iQue)", if it is a known one
+    pub fn release_name(&self) -> Option<String> {
+        self.is_known().then(|| {
+            let revision = self.revision().unwrap();
+            let name = format!("{}.{}{}", self.major(), self.minor(), revision);
+            if self.ique == Some(true) && revision == IQUE_REVISION {
+                format!("{name} (iQue)")
+            } else {
+                name
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for LibultraVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.release_name() {
+            Some(name) => write!(f, "{name}"),
+            None => write!(
+                f,
+                "unknown ({:04X} {:02X} {:02X})",
+                self.reserved, self.release, self.revision
+            ),
+        }
+    }
+}
+
+impl Serialize for LibultraVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("LibultraVersion", 5)?;
+        state.serialize_field("name", &self.release_name())?;
+        state.serialize_field("reserved", &self.reserved)?;
+        state.serialize_field("release", &self.release)?;
+        state.serialize_field("revision", &self.revision())?;
+        state.serialize_field("ique", &self.ique)?;
+        state.end()
+    }
+}
+
+/// Check that the header's libultra claim fits how the ROM was built. Only the SDK's makerom
+/// fills in the revision word, and the SDK came with IDO, so a claim without code or IDO code
+/// without a claim is suspicious. iQue games were all built with the iQue SDK's 2.0L, so any
+/// other claim on an iQue image is too. The 2.0D to 2.0K releases leave nothing in the boot segment to tell them
+/// apart, so those claims are only checked for being a known release.
+pub fn check_sdk_build(version: &LibultraVersion, guess: &CompilerGuess) -> Option<String> {
+    let is_ique_release = version.is_known() && version.revision() == Some(IQUE_REVISION);
+    if version.is_ique() == Some(true) && !is_ique_release {
+        Some(format!(
+            "iQue CIC but header claims libultra {version} rather than 2.0{IQUE_REVISION}"
+        ))
+    } else if version.is_known() && guess.text_end.is_none() {
+        Some(format!(
+            "Header claims libultra {version} but the boot segment has no code"
+        ))
+    } else if !version.is_known() && guess.compiler() == Compiler::Ido {
+        Some(format!(
+            "Boot segment looks IDO-compiled but header has no known libultra release: {version}"
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDO: CompilerGuess = CompilerGuess {
+        examined: 0x1000,
+        text_end: Some(0x2000),
+        b_count: 150,
+        j_count: 50,
+    };
+
+    #[test]
+    fn release_names() {
+        assert_eq!(
+            LibultraVersion::new(0x144B, None).release_name().unwrap(),
+            "2.0K"
+        );
+        assert_eq!(
+            LibultraVersion::new(0x144C, Some(true))
+                .release_name()
+                .unwrap(),
+            "2.0L (iQue)"
+        );
+        // Only 2.0L has an iQue build
+        assert_eq!(
+            LibultraVersion::new(0x144B, Some(true))
+                .release_name()
+                .unwrap(),
+            "2.0K"
+        );
+        assert_eq!(LibultraVersion::new(0x0001_144B, None).release_name(), None);
+        assert_eq!(LibultraVersion::new(0x144D, None).major(), 2);
+        assert_eq!(LibultraVersion::new(0x1441, None).release_name(), None);
+    }
+
+    #[test]
+    fn ique_claims_must_be_2_0l() {
+        assert_eq!(
+            check_sdk_build(&LibultraVersion::new(0x144C, Some(true)), &IDO),
+            None
+        );
+        assert_eq!(
+            check_sdk_build(&LibultraVersion::new(0x144B, None), &IDO),
+            None
+        );
+        assert!(check_sdk_build(&LibultraVersion::new(0x144B, Some(true)), &IDO).is_some());
+        assert!(check_sdk_build(&LibultraVersion::new(0, Some(true)), &IDO).is_some());
+        assert!(check_sdk_build(&LibultraVersion::new(0, Some(false)), &IDO).is_some());
+    }
+}
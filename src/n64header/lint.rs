@@ -0,0 +1,151 @@
+//! Sanity checks on every header field, for spotting bad dumps and hand-made headers
+
+use super::N64Header;
+
+/// KSEG0, where every retail entrypoint lives
+const KSEG0: std::ops::Range<u32> = 0x80000000..0xA0000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Unusual but harmless, e.g. a field homebrew tools leave blank
+    Info,
+    /// Probably wrong, but the ROM may still boot
+    Warning,
+    /// The ROM cannot boot as described
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Severity::Info => "info",
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            }
+        )
+    }
+}
+
+/// Something suspicious about one header field
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, field: &'static str, message: String) -> Finding {
+        Finding {
+            severity,
+            field,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.field, self.message)
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let hex: Vec<_> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+    hex.join(" ")
+}
+
+/// Check every field of the header, most severe findings first
+pub fn lint(header: &N64Header) -> Vec<Finding> {
+    use Severity::*;
+    let mut findings = Vec::new();
+
+    if !KSEG0.contains(&header.entrypoint) {
+        findings.push(Finding::new(
+            Error,
+            "entrypoint",
+            format!("{:08X} is outside KSEG0", header.entrypoint),
+        ));
+    }
+
+    let libultra = header.libultra();
+    if libultra.reserved() != 0 {
+        findings.push(Finding::new(
+            Warning,
+            "revision",
+            format!("reserved bytes are set: {:08X}", header.revision),
+        ));
+    } else if !libultra.is_known() {
+        findings.push(Finding::new(
+            Info,
+            "revision",
+            format!("{:08X} is not a known libultra release", header.revision),
+        ));
+    }
+
+    if header.unk_18 != [0; 8] {
+        findings.push(Finding::new(
+            Warning,
+            "unk_18",
+            format!("reserved bytes are set: {}", hex_bytes(&header.unk_18)),
+        ));
+    }
+    if header.unk_34 != [0; 4] {
+        // The advanced homebrew header puts controller types here
+        let severity = if header.homebrew().is_some() {
+            Info
+        } else {
+            Warning
+        };
+        findings.push(Finding::new(
+            severity,
+            "unk_34",
+            format!("reserved bytes are set: {}", hex_bytes(&header.unk_34)),
+        ));
+    }
+
+    let image_name = header.image_name();
+    if image_name.contains('\u{FFFD}') {
+        findings.push(Finding::new(
+            Warning,
+            "image_name",
+            "is not valid Shift-JIS".to_string(),
+        ));
+    }
+    if image_name
+        .trim_end_matches(['\0', ' '])
+        .chars()
+        .any(char::is_control)
+    {
+        findings.push(Finding::new(
+            Warning,
+            "image_name",
+            format!("contains non-printable characters: {image_name:?}"),
+        ));
+    }
+    if image_name.trim_end_matches(['\0', ' ']).is_empty() {
+        findings.push(Finding::new(Info, "image_name", "is blank".to_string()));
+    }
+
+    if let Err(err) = header.media_format_description() {
+        findings.push(Finding::new(
+            Warning,
+            "media_format",
+            format!("{err}: {:08X}", header.media_format),
+        ));
+    }
+    if let Err(err) = header.country_code_description() {
+        findings.push(Finding::new(
+            Warning,
+            "country_code",
+            format!("{err}: {:02X}", header.country_code),
+        ));
+    }
+
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+    findings
+}
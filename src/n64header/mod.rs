@@ -1,31 +1,99 @@
+pub mod detect;
 pub mod entrypoint;
+pub mod homebrew;
 pub mod ipl3;
+pub mod libultra;
+pub mod lint;
 
-use std::error::Error;
+use crate::error::{self, Error};
+use homebrew::{HomebrewFlags, HOMEBREW_CARTRIDGE_ID};
+use libultra::LibultraVersion;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use encoding_rs;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 // use std::env;
 // use std::fs;
 use std::io;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
 pub enum Endian {
+    /// Big-endian, .z64
+    #[serde(rename = "z64")]
     Good,
+    /// Little-endian, .n64
+    #[serde(rename = "n64")]
     Bad,
+    /// Byteswapped, .v64
+    #[serde(rename = "v64")]
     Ugly,
 }
 
-pub fn get_endian(input: &[u8]) -> Result<Endian, Box<dyn Error>> {
-    match &input[0..4] {
-        [0x80, 0x37, 0x12, 0x40] => Ok(Endian::Good),
-        [0x40, 0x12, 0x37, 0x80] => Ok(Endian::Bad),
-        [0x37, 0x80, 0x40, 0x12] => Ok(Endian::Ugly),
-        _ => panic!("Unrecognised header format"),
+impl Endian {
+    /// Conventional file extension for a ROM in this byte order
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            Endian::Good => "z64",
+            Endian::Bad => "n64",
+            Endian::Ugly => "v64",
+        }
+    }
+}
+
+/// Byte order of a ROM from its first bytes. Anything but the standard PI magic needs
+/// `detect::DETECT_LEN` bytes to be guessed from the code.
+pub fn get_endian(input: &[u8]) -> error::Result<Endian> {
+    detect::detect_endian(input).map(|guess| guess.endian)
+}
+
+/// PI BSD DOM1 register values almost every retail cartridge uses
+pub const STANDARD_PI_BSD_DOM1: [u8; 4] = [0x80, 0x37, 0x12, 0x40];
+/// Clock rate word that leaves libultra's default in place
+pub const STANDARD_CLOCK_RATE: u32 = 0x0000000F;
+
+/// Cartridge bus timing IPL2 programs into the PI for domain 1 before reading the rest of the ROM
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct PiTiming {
+    /// RCP cycles between the address and the first read, less one
+    pub latency: u8,
+    /// RCP cycles the read strobe is held, less one
+    pub pulse_width: u8,
+    /// Page size as a power of two, less two
+    pub page_size: u8,
+    /// RCP cycles the strobe is released between reads, less one
+    pub release: u8,
+}
+
+impl PiTiming {
+    const fn from_register(register: [u8; 4]) -> PiTiming {
+        PiTiming {
+            latency: register[3],
+            pulse_width: register[2],
+            page_size: register[1] & 0x0F,
+            release: (register[1] >> 4) & 0x03,
+        }
+    }
+
+    /// Bytes the PI reads before it has to send a new address
+    pub const fn page_bytes(&self) -> u32 {
+        1 << (self.page_size + 2)
     }
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for PiTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "latency {} cycles, pulse width {} cycles, page size {} bytes, release {} cycles",
+            self.latency as u32 + 1,
+            self.pulse_width as u32 + 1,
+            self.page_bytes(),
+            self.release + 1
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct N64Header {
     /* 0x00 */ pibsddomain1_register: [u8; 4],
     /* 0x04 */ clock_rate: u32,
@@ -75,14 +143,74 @@ impl N64Header {
         }
     }
 
+    pub fn clock_rate(&self) -> u32 {
+        self.clock_rate
+    }
+
+    /// Clock rate libultra is told to use instead of its default, if any. The low nibble is ignored.
+    pub fn clock_rate_override(&self) -> Option<u32> {
+        Some(self.clock_rate & !0xF).filter(|rate| *rate != 0)
+    }
+
+    pub fn pi_timing(&self) -> PiTiming {
+        PiTiming::from_register(self.pibsddomain1_register)
+    }
+
+    /// Check every field for anything suspicious
+    pub fn lint(&self) -> Vec<lint::Finding> {
+        lint::lint(self)
+    }
+
+    /// Warn about bus timing or clock rate that differ from retail cartridges
+    pub fn timing_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.pibsddomain1_register != STANDARD_PI_BSD_DOM1 {
+            warnings.push(format!(
+                "Non-standard PI BSD DOM1 register {:08X} ({})",
+                u32::from_be_bytes(self.pibsddomain1_register),
+                self.pi_timing()
+            ));
+        }
+        if self.clock_rate != STANDARD_CLOCK_RATE {
+            warnings.push(match self.clock_rate_override() {
+                Some(rate) => format!(
+                    "Clock rate overridden to {rate} Hz ({:08X})",
+                    self.clock_rate
+                ),
+                None => format!("Non-standard clock rate {:08X}", self.clock_rate),
+            });
+        }
+        warnings
+    }
+
     pub fn entrypoint(&self) -> u32 {
         self.entrypoint
     }
 
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
     pub fn libultra_version(&self) -> Option<char> {
         char::from_u32(self.revision & 0xFF)
     }
 
+    /// The whole revision word decoded as a libultra release. The header alone cannot tell an iQue
+    /// build apart, see [`Rom::libultra`](crate::Rom::libultra).
+    pub fn libultra(&self) -> LibultraVersion {
+        LibultraVersion::new(self.revision, None)
+    }
+
+    /// Whether the country code is the blank or Chinese one iQue Player games use. Homebrew and
+    /// prototypes often leave it blank too, so on its own this only hints at an iQue image.
+    pub fn has_ique_country_code(&self) -> bool {
+        matches!(self.country_code, 0 | b'C')
+    }
+
     pub fn image_name(&self) -> String {
         encoding_rs::SHIFT_JIS
             .decode(&self.image_name)
@@ -101,14 +229,97 @@ impl N64Header {
     }
 
     pub fn country_code(&self) -> char {
-        char::from_u32(self.country_code as u32)
-            .expect(format!("could not parse country code \"{:X}\"", self.media_format).as_str())
+        char::from(self.country_code)
     }
 
     pub fn checksum(&self) -> (u32, u32) {
         (self.checksum1, self.checksum2)
     }
 
+    /// Save type, RTC and region flags, if the ROM uses the advanced homebrew header
+    pub fn homebrew(&self) -> Option<HomebrewFlags> {
+        (self.cartridge_id == HOMEBREW_CARTRIDGE_ID).then(|| HomebrewFlags::from_byte(self.version))
+    }
+
+    /// Set the image name, encoded as Shift-JIS and padded with spaces
+    pub fn set_image_name(&mut self, name: &str) -> error::Result<()> {
+        let (encoded, _, unmappable) = encoding_rs::SHIFT_JIS.encode(name);
+        if unmappable {
+            return Err(Error::InvalidHeaderField(
+                "image_name",
+                format!("\"{name}\" cannot be encoded as Shift-JIS"),
+            ));
+        }
+        if encoded.len() > self.image_name.len() {
+            return Err(Error::InvalidHeaderField(
+                "image_name",
+                format!(
+                    "\"{name}\" is {} bytes in Shift-JIS, the maximum is {}",
+                    encoded.len(),
+                    self.image_name.len()
+                ),
+            ));
+        }
+        self.image_name = [b' '; 20];
+        self.image_name[..encoded.len()].copy_from_slice(&encoded);
+        Ok(())
+    }
+
+    pub fn set_cartridge_id(&mut self, id: &str) -> error::Result<()> {
+        self.cartridge_id = id.as_bytes().try_into().map_err(|_| {
+            Error::InvalidHeaderField("cartridge_id", format!("\"{id}\" is not 2 bytes"))
+        })?;
+        Ok(())
+    }
+
+    pub fn set_country_code(&mut self, code: char) -> error::Result<()> {
+        self.country_code = ascii_byte("country_code", code)?;
+        Ok(())
+    }
+
+    pub fn set_media_format(&mut self, format: char) -> error::Result<()> {
+        self.media_format =
+            (self.media_format & !0xFF) | ascii_byte("media_format", format)? as u32;
+        Ok(())
+    }
+
+    pub fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+
+    /// Switch to the advanced homebrew header, which replaces the cartridge ID and version
+    pub fn set_homebrew(&mut self, flags: HomebrewFlags) {
+        self.cartridge_id = HOMEBREW_CARTRIDGE_ID;
+        self.version = flags.to_byte();
+    }
+
+    pub fn set_checksum(&mut self, checksum: (u32, u32)) {
+        (self.checksum1, self.checksum2) = checksum;
+    }
+
+    /// Big-endian bytes of the header, the inverse of `read_header`
+    pub fn to_bytes(&self) -> [u8; 0x40] {
+        let mut bytes = [0u8; 0x40];
+        self.write_header(&mut bytes[..]).unwrap();
+        bytes
+    }
+
+    pub fn write_header(&self, mut writer: impl io::Write) -> io::Result<()> {
+        writer.write_all(&self.pibsddomain1_register)?;
+        writer.write_u32::<BigEndian>(self.clock_rate)?;
+        writer.write_u32::<BigEndian>(self.entrypoint)?;
+        writer.write_u32::<BigEndian>(self.revision)?;
+        writer.write_u32::<BigEndian>(self.checksum1)?;
+        writer.write_u32::<BigEndian>(self.checksum2)?;
+        writer.write_all(&self.unk_18)?;
+        writer.write_all(&self.image_name)?;
+        writer.write_all(&self.unk_34)?;
+        writer.write_u32::<BigEndian>(self.media_format)?;
+        writer.write_all(&self.cartridge_id)?;
+        writer.write_u8(self.country_code)?;
+        writer.write_u8(self.version)
+    }
+
     pub fn media_format_description(&self) -> Result<&'static str, &'static str> {
         Ok(match self.media_format() {
             'N' => "cartridge",
@@ -148,6 +359,13 @@ impl N64Header {
     }
 }
 
+fn ascii_byte(field: &'static str, c: char) -> error::Result<u8> {
+    u8::try_from(c)
+        .ok()
+        .filter(u8::is_ascii)
+        .ok_or_else(|| Error::InvalidHeaderField(field, format!("'{c}' is not ASCII")))
+}
+
 pub fn read_header(mut reader: impl io::Read) -> io::Result<N64Header> {
     let mut pibsddomain1_register = [0u8; 4];
     reader.read_exact(&mut pibsddomain1_register)?;
@@ -187,6 +405,36 @@ pub fn read_header(mut reader: impl io::Read) -> io::Result<N64Header> {
     ))
 }
 
+impl Serialize for N64Header {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("N64Header", 17)?;
+        state.serialize_field(
+            "pibsddomain1_register",
+            &u32::from_be_bytes(self.pibsddomain1_register),
+        )?;
+        state.serialize_field("pi_timing", &self.pi_timing())?;
+        state.serialize_field("clock_rate", &self.clock_rate)?;
+        state.serialize_field("clock_rate_override", &self.clock_rate_override())?;
+        state.serialize_field("entrypoint", &self.entrypoint)?;
+        state.serialize_field("revision", &self.revision)?;
+        state.serialize_field("libultra_version", &self.libultra_version())?;
+        state.serialize_field("libultra", &self.libultra())?;
+        state.serialize_field("checksum1", &self.checksum1)?;
+        state.serialize_field("checksum2", &self.checksum2)?;
+        state.serialize_field("image_name", &self.image_name())?;
+        state.serialize_field("media_format", &self.media_format())?;
+        state.serialize_field("cartridge_id", &self.cartridge_id())?;
+        state.serialize_field("country_code", &self.country_code())?;
+        state.serialize_field(
+            "country_code_description",
+            &self.country_code_description().ok(),
+        )?;
+        state.serialize_field("version", &self.version)?;
+        state.serialize_field("homebrew", &self.homebrew())?;
+        state.end()
+    }
+}
+
 /// Use alternate format to get full version, normal for CSV of
 /// clock_rate, entrypoint, revision, checksum, image_name, media_format, cartridge_id, country_code, version
 impl std::fmt::Display for N64Header {
@@ -194,10 +442,10 @@ impl std::fmt::Display for N64Header {
         if f.alternate() {
             write!(
                 f,
-                "pibsddomain1_register:  {:02X} {:02X} {:02X} {:02X}\n\
-                clock_rate:             {:08X}\n\
+                "pibsddomain1_register:  {:02X} {:02X} {:02X} {:02X} ({})\n\
+                clock_rate:             {:08X} ({})\n\
                 reported_entrypoint:    {:08X}\n\
-                revision:               {:08X}\n\
+                revision:               {:08X} (libultra {})\n\
                 checksum:               {:08X} {:08X}\n\
                 unk_18:                 {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X}\n\
                 image_name:             \"{}\"\n\
@@ -210,9 +458,15 @@ impl std::fmt::Display for N64Header {
                 self.pibsddomain1_register[1],
                 self.pibsddomain1_register[2],
                 self.pibsddomain1_register[3],
+                self.pi_timing(),
                 self.clock_rate,
+                match self.clock_rate_override() {
+                    Some(rate) => format!("{rate} Hz"),
+                    None => "default".to_string(),
+                },
                 self.entrypoint,
                 self.revision,
+                self.libultra(),
                 self.checksum().0,
                 self.checksum().1,
                 self.unk_18[0],
@@ -229,12 +483,23 @@ impl std::fmt::Display for N64Header {
                 self.unk_34[2],
                 self.unk_34[3],
                 self.media_format(),
-                self.media_format_description().unwrap(),
+                self.media_format_description().unwrap_or_else(|err| err),
                 self.cartridge_id(),
-                self.country_code(),
-                self.country_code_description().unwrap(),
+                self.country_code().escape_default(),
+                self.country_code_description().unwrap_or_else(|err| err),
                 self.version,
-            )
+            )?;
+            if let Some(flags) = self.homebrew() {
+                write!(
+                    f,
+                    "\n\
+                    save_type:              {}\n\
+                    rtc:                    {}\n\
+                    region_free:            {}",
+                    flags.save_type, flags.rtc, flags.region_free,
+                )?;
+            }
+            Ok(())
         } else {
             write!(
                 f,
@@ -247,9 +512,43 @@ impl std::fmt::Display for N64Header {
                 self.image_name(),
                 self.media_format(),
                 self.cartridge_id(),
-                self.country_code(),
+                self.country_code().escape_default(),
                 self.version
             )
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+
+    #[test]
+    fn bytes_round_trip() {
+        for seed in 0..8 {
+            let bytes: [u8; 0x40] = noise(0x40, seed).try_into().unwrap();
+            assert_eq!(read_header(&bytes[..]).unwrap().to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn edits_round_trip() {
+        let mut header = read_header(&[0; 0x40][..]).unwrap();
+        header.set_image_name("ゼルダの伝説").unwrap();
+        header.set_cartridge_id("ZL").unwrap();
+        header.set_country_code('J').unwrap();
+        header.set_media_format('N').unwrap();
+        header.set_version(2);
+        header.set_checksum((0x12345678, 0x9ABCDEF0));
+
+        let read = read_header(&header.to_bytes()[..]).unwrap();
+        assert_eq!(read.image_name().trim_end(), "ゼルダの伝説");
+        assert_eq!(read.cartridge_id(), "ZL");
+        assert_eq!(read.country_code(), 'J');
+        assert_eq!(read.media_format(), 'N');
+        assert_eq!(read.version(), 2);
+        assert_eq!(read.checksum(), (0x12345678, 0x9ABCDEF0));
+        assert_eq!(read.to_bytes(), header.to_bytes());
+    }
+}
@@ -0,0 +1,513 @@
+//! Applying and creating IPS and BPS patches. Both work on big-endian images, so patches made
+//! against a .z64 apply whatever byte order the ROM is in.
+
+use crate::error::{Error, Result};
+use crate::size::MAX_CART_SIZE;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+/// An IPS record here would read as the end marker
+const IPS_EOF_OFFSET: usize = 0x454F46;
+/// IPS offsets are 24 bits
+const IPS_MAX_OFFSET: usize = 0xFFFFFF;
+const IPS_MAX_RECORD: usize = 0xFFFF;
+
+const BPS_MAGIC: &[u8] = b"BPS1";
+const BPS_FOOTER_SIZE: usize = 12;
+const BPS_SOURCE_READ: u64 = 0;
+const BPS_TARGET_READ: u64 = 1;
+const BPS_SOURCE_COPY: u64 = 2;
+const BPS_TARGET_COPY: u64 = 3;
+const OUT_OF_RANGE: Error = Error::InvalidPatch("copy outside the image");
+
+/// Unchanged bytes worth breaking a run of changes for, rather than carrying them along
+const MIN_UNCHANGED_RUN: usize = 8;
+
+const CRC_ALG: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatchFormat {
+    Ips,
+    Bps,
+}
+
+impl PatchFormat {
+    /// Recognise a patch from its magic
+    pub fn detect(patch: &[u8]) -> Result<PatchFormat> {
+        if patch.starts_with(IPS_MAGIC) {
+            Ok(PatchFormat::Ips)
+        } else if patch.starts_with(BPS_MAGIC) {
+            Ok(PatchFormat::Bps)
+        } else {
+            Err(Error::InvalidPatch("not an IPS or BPS patch"))
+        }
+    }
+}
+
+/// Apply an IPS or BPS patch to `source`, returning the patched image
+pub fn apply(patch: &[u8], source: &[u8]) -> Result<Vec<u8>> {
+    match PatchFormat::detect(patch)? {
+        PatchFormat::Ips => apply_ips(patch, source),
+        PatchFormat::Bps => apply_bps(patch, source),
+    }
+}
+
+/// Make a patch that turns `source` into `target`
+pub fn create(format: PatchFormat, source: &[u8], target: &[u8]) -> Result<Vec<u8>> {
+    match format {
+        PatchFormat::Ips => create_ips(source, target),
+        PatchFormat::Bps => Ok(create_bps(source, target)),
+    }
+}
+
+/// Reads the fields of a patch in order, failing cleanly if it ends early
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> PatchReader<'a> {
+        PatchReader { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .ok_or(Error::InvalidPatch("patch ends early"))?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(Error::InvalidPatch("patch ends early"))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Big-endian number `len` bytes long
+    fn be(&mut self, len: usize) -> Result<usize> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    /// BPS variable-length number
+    fn varint(&mut self) -> Result<u64> {
+        let mut value: u64 = 0;
+        let mut shift: u64 = 1;
+        loop {
+            let byte = self.bytes(1)?[0];
+            value = (byte as u64 & 0x7F)
+                .checked_mul(shift)
+                .and_then(|part| value.checked_add(part))
+                .ok_or(Error::InvalidPatch("number too large"))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift
+                .checked_mul(0x80)
+                .filter(|&shift| value.checked_add(shift).is_some())
+                .ok_or(Error::InvalidPatch("number too large"))?;
+            value += shift;
+        }
+    }
+
+    /// BPS relative offset, stored as magnitude and sign
+    fn signed_varint(&mut self) -> Result<i64> {
+        let value = self.varint()?;
+        let magnitude = (value >> 1) as i64;
+        Ok(if value & 1 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        })
+    }
+}
+
+fn apply_ips(patch: &[u8], source: &[u8]) -> Result<Vec<u8>> {
+    let mut target = source.to_vec();
+    let mut reader = PatchReader::new(patch, IPS_MAGIC.len());
+
+    loop {
+        if reader.bytes(3)? == IPS_EOF {
+            break;
+        }
+        reader.pos -= 3;
+        let offset = reader.be(3)?;
+        let len = reader.be(2)?;
+        let (len, record) = if len == 0 {
+            // Run-length encoded record
+            let len = reader.be(2)?;
+            (len, vec![reader.bytes(1)?[0]; len])
+        } else {
+            (len, reader.bytes(len)?.to_vec())
+        };
+        if target.len() < offset + len {
+            target.resize(offset + len, 0);
+        }
+        target[offset..offset + len].copy_from_slice(&record);
+    }
+
+    // Optional truncation extension
+    if let Ok(size) = reader.be(3) {
+        target.truncate(size);
+    }
+    Ok(target)
+}
+
+fn create_ips(source: &[u8], target: &[u8]) -> Result<Vec<u8>> {
+    if target.len() > IPS_MAX_OFFSET + 1 {
+        return Err(Error::InvalidPatch("IPS cannot address more than 16 MiB"));
+    }
+    let mut patch = IPS_MAGIC.to_vec();
+    let changed = |i: usize| source.get(i) != Some(&target[i]);
+
+    let mut pos = 0;
+    while pos < target.len() {
+        if !changed(pos) {
+            pos += 1;
+            continue;
+        }
+        // A record starting at the EOF offset would end the patch, so start one byte sooner
+        let start = if pos == IPS_EOF_OFFSET { pos - 1 } else { pos };
+        let mut end = pos;
+        let mut unchanged = 0;
+        while end < target.len() && end - start < IPS_MAX_RECORD && unchanged < MIN_UNCHANGED_RUN {
+            unchanged = if changed(end) { 0 } else { unchanged + 1 };
+            end += 1;
+        }
+        let end = end - unchanged;
+
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&target[start..end]);
+        pos = end;
+    }
+
+    patch.extend_from_slice(IPS_EOF);
+    if target.len() < source.len() {
+        patch.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(patch)
+}
+
+fn apply_bps(patch: &[u8], source: &[u8]) -> Result<Vec<u8>> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SIZE {
+        return Err(Error::InvalidPatch("patch ends early"));
+    }
+    let actions_end = patch.len() - BPS_FOOTER_SIZE;
+    let mut footer = PatchReader::new(patch, actions_end);
+    let crc = |reader: &mut PatchReader| -> Result<u32> {
+        Ok(u32::from_le_bytes(reader.bytes(4)?.try_into().unwrap()))
+    };
+    let source_crc = crc(&mut footer)?;
+    let target_crc = crc(&mut footer)?;
+    let patch_crc = crc(&mut footer)?;
+
+    let check = |kind, expected, data: &[u8]| {
+        let actual = CRC_ALG.checksum(data);
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(Error::PatchCrcMismatch {
+                kind,
+                expected,
+                actual,
+            })
+        }
+    };
+    check("patch", patch_crc, &patch[..patch.len() - 4])?;
+    check("source", source_crc, source)?;
+
+    let mut reader = PatchReader::new(&patch[..actions_end], BPS_MAGIC.len());
+    let source_size = reader.varint()? as usize;
+    let target_size = reader.varint()? as usize;
+    let metadata_size = reader.varint()? as usize;
+    reader.bytes(metadata_size)?;
+    if source_size != source.len() {
+        return Err(Error::InvalidPatch(
+            "source is not the size the patch expects",
+        ));
+    }
+    if target_size as u64 > MAX_CART_SIZE {
+        return Err(Error::InvalidPatch("target is bigger than any cart"));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    while reader.pos < actions_end {
+        let action = reader.varint()?;
+        let len = (action >> 2) as usize + 1;
+        if len > target_size - target.len() {
+            return Err(Error::InvalidPatch(
+                "actions write past the end of the target",
+            ));
+        }
+        match action & 3 {
+            BPS_SOURCE_READ => {
+                let start = target.len();
+                target.extend_from_slice(source.get(start..start + len).ok_or(OUT_OF_RANGE)?);
+            }
+            BPS_TARGET_READ => target.extend_from_slice(reader.bytes(len)?),
+            BPS_SOURCE_COPY => {
+                source_offset = relative(source_offset, reader.signed_varint()?)?;
+                let copy = source
+                    .get(source_offset..)
+                    .and_then(|rest| rest.get(..len))
+                    .ok_or(OUT_OF_RANGE)?;
+                target.extend_from_slice(copy);
+                source_offset += len;
+            }
+            BPS_TARGET_COPY => {
+                target_offset = relative(target_offset, reader.signed_varint()?)?;
+                if target_offset >= target.len() {
+                    return Err(OUT_OF_RANGE);
+                }
+                // May overlap what it is writing, repeating the bytes, so copy one at a time
+                for i in target_offset..target_offset + len {
+                    target.push(target[i]);
+                }
+                target_offset += len;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(Error::InvalidPatch("target is not the size the patch says"));
+    }
+    check("target", target_crc, &target)?;
+    Ok(target)
+}
+
+/// Move a BPS copy offset, which must stay within the image
+fn relative(offset: usize, delta: i64) -> Result<usize> {
+    offset
+        .checked_add_signed(delta as isize)
+        .ok_or(OUT_OF_RANGE)
+}
+
+fn push_varint(patch: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            patch.push(0x80 | byte);
+            return;
+        }
+        patch.push(byte);
+        value -= 1;
+    }
+}
+
+/// Encode runs the source already has as source reads and everything else as target reads
+fn create_bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    push_varint(&mut patch, source.len() as u64);
+    push_varint(&mut patch, target.len() as u64);
+    push_varint(&mut patch, 0);
+
+    let same = |i: usize| source.get(i) == Some(&target[i]);
+    let mut pos = 0;
+    while pos < target.len() {
+        let start = pos;
+        if same(pos) {
+            while pos < target.len() && same(pos) {
+                pos += 1;
+            }
+            push_varint(
+                &mut patch,
+                ((pos - start - 1) as u64) << 2 | BPS_SOURCE_READ,
+            );
+        } else {
+            // Carry short matches along rather than switching actions for them
+            let mut unchanged = 0;
+            while pos < target.len() && unchanged < MIN_UNCHANGED_RUN {
+                unchanged = if same(pos) { unchanged + 1 } else { 0 };
+                pos += 1;
+            }
+            pos -= unchanged;
+            push_varint(
+                &mut patch,
+                ((pos - start - 1) as u64) << 2 | BPS_TARGET_READ,
+            );
+            patch.extend_from_slice(&target[start..pos]);
+        }
+    }
+
+    patch.extend_from_slice(&CRC_ALG.checksum(source).to_le_bytes());
+    patch.extend_from_slice(&CRC_ALG.checksum(target).to_le_bytes());
+    let patch_crc = CRC_ALG.checksum(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+
+    /// `source` with scattered single-byte edits, a run of edits and a different length
+    fn edited(source: &[u8], len: usize) -> Vec<u8> {
+        let mut target = source.to_vec();
+        target.resize(len, 0xFF);
+        for i in (0x10..len.min(source.len())).step_by(0x123) {
+            target[i] ^= 0x5A;
+        }
+        let run = len / 2;
+        target[run..run + 0x40].fill(0);
+        target
+    }
+
+    /// Finish a BPS patch body with correct CRCs, so only the body is under test
+    fn bps_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&CRC_ALG.checksum(source).to_le_bytes());
+        patch.extend_from_slice(&CRC_ALG.checksum(target).to_le_bytes());
+        let patch_crc = CRC_ALG.checksum(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [
+            0,
+            1,
+            0x7F,
+            0x80,
+            0x407F,
+            0x4080,
+            300,
+            1 << 40,
+            u64::MAX >> 1,
+        ] {
+            let mut encoded = Vec::new();
+            push_varint(&mut encoded, value);
+            let mut reader = PatchReader::new(&encoded, 0);
+            assert_eq!(reader.varint().unwrap(), value);
+            assert_eq!(reader.pos, encoded.len());
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = noise(0x10000, 1);
+        for len in [0x10000, 0x12345, 0x8000] {
+            let target = edited(&source, len);
+            for format in [PatchFormat::Ips, PatchFormat::Bps] {
+                let patch = create(format, &source, &target).unwrap();
+                assert_eq!(PatchFormat::detect(&patch).unwrap(), format);
+                assert_eq!(apply(&patch, &source).unwrap(), target);
+            }
+        }
+    }
+
+    #[test]
+    fn ips_rle_record() {
+        let patch = b"PATCH\x00\x00\x02\x00\x00\x00\x04\xAAEOF";
+        assert_eq!(
+            apply(patch, &[1; 8]).unwrap(),
+            [1, 1, 0xAA, 0xAA, 0xAA, 0xAA, 1, 1]
+        );
+    }
+
+    #[test]
+    fn ips_record_at_eof_offset() {
+        let source = vec![0; IPS_EOF_OFFSET + 0x10];
+        let mut target = source.clone();
+        target[IPS_EOF_OFFSET] = 1;
+        let patch = create(PatchFormat::Ips, &source, &target).unwrap();
+        assert!(!patch[IPS_MAGIC.len()..].starts_with(&[0x45, 0x4F, 0x46]));
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+    }
+
+    #[test]
+    fn ips_truncation() {
+        let patch = b"PATCHEOF\x00\x00\x03";
+        assert_eq!(apply(patch, &[1, 2, 3, 4, 5]).unwrap(), [1, 2, 3]);
+    }
+
+    /// Made independently of this module, using each of the four actions, the last an
+    /// overlapping target copy
+    const HELLO_BPS: [u8; 29] = [
+        0x42, 0x50, 0x53, 0x31, 0x8D, 0x93, 0x80, 0x90, 0x85, 0x2C, 0x20, 0x92, 0x8E, 0x81, 0x21,
+        0x97, 0x98, 0xE6, 0xC6, 0xE6, 0xEB, 0xF4, 0x54, 0x8F, 0x90, 0xE5, 0xEC, 0x56, 0xE6,
+    ];
+
+    #[test]
+    fn bps_known_vector() {
+        assert_eq!(
+            apply(&HELLO_BPS, b"Hello, world!").unwrap(),
+            b"Hello, world!!!!!!!"
+        );
+    }
+
+    #[test]
+    fn bps_crc_mismatch() {
+        assert!(matches!(
+            apply(&HELLO_BPS, b"Hello, World!"),
+            Err(Error::PatchCrcMismatch { kind: "source", .. })
+        ));
+
+        let mut corrupt = HELLO_BPS;
+        corrupt[10] ^= 1;
+        assert!(matches!(
+            apply(&corrupt, b"Hello, world!"),
+            Err(Error::PatchCrcMismatch { kind: "patch", .. })
+        ));
+    }
+
+    #[test]
+    fn bps_crafted_sizes() {
+        let source = [0u8; 4];
+        let header = |target_size: u64, metadata_size: u64| {
+            let mut patch = BPS_MAGIC.to_vec();
+            push_varint(&mut patch, source.len() as u64);
+            push_varint(&mut patch, target_size);
+            push_varint(&mut patch, metadata_size);
+            patch
+        };
+
+        // Metadata far longer than the patch
+        let patch = bps_footer(header(4, u64::MAX >> 1), &source, &source);
+        assert!(matches!(
+            apply(&patch, &source),
+            Err(Error::InvalidPatch(_))
+        ));
+
+        // A target too big to allocate, filled by a repeating target copy
+        let mut body = header(u64::MAX >> 1, 0);
+        push_varint(&mut body, BPS_TARGET_READ);
+        body.push(0);
+        push_varint(&mut body, (u32::MAX as u64) << 2 | BPS_TARGET_COPY);
+        push_varint(&mut body, 0);
+        let patch = bps_footer(body, &source, &source);
+        assert!(matches!(
+            apply(&patch, &source),
+            Err(Error::InvalidPatch(_))
+        ));
+
+        // A copy longer than the target says
+        let mut body = header(4, 0);
+        push_varint(&mut body, 7 << 2 | BPS_SOURCE_READ);
+        let patch = bps_footer(body, &source, &source);
+        assert!(matches!(
+            apply(&patch, &source),
+            Err(Error::InvalidPatch(_))
+        ));
+
+        // A target copy from before the start
+        let mut body = header(4, 0);
+        push_varint(&mut body, BPS_SOURCE_READ);
+        push_varint(&mut body, BPS_TARGET_COPY);
+        push_varint(&mut body, 1 << 1 | 1);
+        let patch = bps_footer(body, &source, &source);
+        assert!(matches!(
+            apply(&patch, &source),
+            Err(Error::InvalidPatch(_))
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+//! A reader that presents a ROM in any byte order as big-endian, so parsers can read straight
+//! from a file without loading and swapping the whole image first
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::error::Result;
+use crate::n64header::{self, detect, Endian};
+use crate::reend_array;
+
+/// Wraps a ROM in any byte order, reading and seeking as if it were big-endian.
+/// Byte order only makes sense a word at a time, so the inner reader is always read in whole
+/// aligned words, leaving reads and seeks free to start and end anywhere.
+pub struct ReendReader<R> {
+    inner: R,
+    endian: Endian,
+    /// Where the ROM starts in the inner reader
+    start: u64,
+    /// Position in the big-endian view, which need not be word-aligned
+    pos: u64,
+}
+
+impl<R: Read + Seek> ReendReader<R> {
+    /// Detect the byte order of the ROM starting at the current position of `inner`
+    pub fn new(mut inner: R) -> Result<ReendReader<R>> {
+        let start = inner.stream_position()?;
+        let prefix = detect::read_detect_prefix(&mut inner)?;
+        inner.seek(SeekFrom::Start(start))?;
+        let endian = n64header::get_endian(&prefix)?;
+        ReendReader::with_endian(inner, endian)
+    }
+
+    /// Read the ROM starting at the current position of `inner` as being in byte order `endian`
+    pub fn with_endian(mut inner: R, endian: Endian) -> Result<ReendReader<R>> {
+        let start = inner.stream_position()?;
+        Ok(ReendReader {
+            inner,
+            endian,
+            start,
+            pos: 0,
+        })
+    }
+
+    /// Byte order of the underlying ROM
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> Read for ReendReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let word_start = self.pos & !3;
+        let skip = (self.pos - word_start) as usize;
+        let mut words = vec![0u8; (skip + buf.len() + 3) & !3];
+
+        self.inner.seek(SeekFrom::Start(self.start + word_start))?;
+        let mut filled = 0;
+        while filled < words.len() {
+            match self.inner.read(&mut words[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        // Swap a short last word as if padded with zeros, but stop where the data does
+        let padded = (filled + 3) & !3;
+        reend_array(&mut words[..padded], &self.endian);
+
+        let len = filled.saturating_sub(skip).min(buf.len());
+        buf[..len].copy_from_slice(&words[skip..skip + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for ReendReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                let len = self
+                    .inner
+                    .seek(SeekFrom::End(0))?
+                    .saturating_sub(self.start);
+                len.checked_add_signed(offset)
+            }
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        self.pos = new_pos;
+        Ok(new_pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{stored, z64};
+    use std::io::Cursor;
+
+    /// The fixture with the standard PI magic
+    fn rom() -> Vec<u8> {
+        z64(0x80371240)
+    }
+
+    #[test]
+    fn unaligned_reads() {
+        let expected = rom();
+        for endian in [Endian::Good, Endian::Ugly, Endian::Bad] {
+            let mut reader = ReendReader::new(Cursor::new(stored(rom(), endian))).unwrap();
+            assert_eq!(reader.endian(), endian);
+            for (start, len) in [
+                (0, 0x40),
+                (1, 2),
+                (3, 5),
+                (0x41, 0x1F),
+                (0x13FD, 3),
+                (0x13FE, 8),
+            ] {
+                let mut buf = vec![0; len];
+                reader.seek(SeekFrom::Start(start as u64)).unwrap();
+                let read = reader.read(&mut buf).unwrap();
+                let end = (start + len).min(expected.len());
+                assert_eq!(
+                    &buf[..read],
+                    &expected[start..end],
+                    "{endian:?} at {start:#X}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn relative_seeks() {
+        let expected = rom();
+        for endian in [Endian::Ugly, Endian::Bad] {
+            let mut reader = ReendReader::new(Cursor::new(stored(rom(), endian))).unwrap();
+            let mut byte = [0];
+            reader.seek(SeekFrom::Start(0x11)).unwrap();
+            reader.seek(SeekFrom::Current(-6)).unwrap();
+            reader.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], expected[0x0B]);
+            assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 0x13FD);
+            reader.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], expected[0x13FD]);
+            assert!(reader.seek(SeekFrom::Current(-0x2000)).is_err());
+        }
+    }
+
+    #[test]
+    fn rom_not_at_start_of_inner() {
+        let expected = rom();
+        let mut inner = vec![0xEE; 5];
+        inner.extend(stored(rom(), Endian::Ugly));
+        let mut cursor = Cursor::new(inner);
+        cursor.seek(SeekFrom::Start(5)).unwrap();
+
+        let mut reader = ReendReader::new(cursor).unwrap();
+        assert_eq!(reader.endian(), Endian::Ugly);
+        let mut buf = [0; 6];
+        reader.seek(SeekFrom::Start(0x3E)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, expected[0x3E..0x44]);
+        assert_eq!(
+            reader.seek(SeekFrom::End(0)).unwrap(),
+            expected.len() as u64
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::n64header::detect::{self, EndianGuess};
+use crate::n64header::ipl3::{self, CICInfo, ChecksumCheck};
+use crate::n64header::libultra::LibultraVersion;
+use crate::n64header::{self, Endian, N64Header};
+use crate::reend_array;
+
+/// ROM offset of the boot segment, which IPL3 copies to the entrypoint
+pub const BOOT_SEGMENT_START: u32 = 0x1000;
+/// Amount of the boot segment IPL3 copies
+pub const BOOT_SEGMENT_SIZE: u32 = 0x100000;
+
+/// A whole ROM image held in memory, normalised to big-endian
+pub struct Rom {
+    data: Vec<u8>,
+    endian_guess: EndianGuess,
+    file_size: u64,
+    header: N64Header,
+    cic_info: CICInfo,
+    entrypoint: u32,
+}
+
+impl Rom {
+    /// Take ownership of a ROM image in any supported byte order
+    pub fn from_bytes(mut data: Vec<u8>) -> Result<Rom> {
+        let file_size = data.len() as u64;
+        if data.len() < 0x40 {
+            return Err(Error::TruncatedRom {
+                offset: 0,
+                len: 0x40,
+            });
+        }
+        let endian_guess = detect::detect_endian(&data)?;
+
+        // Pad to whole words so odd-sized dumps can still be re-ended
+        data.resize((data.len() + 3) & !3, 0);
+        reend_array(&mut data, &endian_guess.endian);
+
+        let header = n64header::read_header(&data[..0x40])?;
+        let cic_info = ipl3::identify(&data)?;
+        let entrypoint = cic_info.correct_entrypoint(header.entrypoint());
+
+        Ok(Rom {
+            data,
+            endian_guess,
+            file_size,
+            header,
+            cic_info,
+            entrypoint,
+        })
+    }
+
+    pub fn load(mut reader: impl Read) -> Result<Rom> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Rom::from_bytes(data)
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Rom> {
+        Rom::load(File::open(path)?)
+    }
+
+    /// Byte order of the original image
+    pub fn endian(&self) -> Endian {
+        self.endian_guess.endian
+    }
+
+    /// How the byte order was worked out, and how sure we are of it
+    pub fn endian_guess(&self) -> EndianGuess {
+        self.endian_guess
+    }
+
+    /// Size of the original image, before any padding
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Whole ROM, big-endian
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn header(&self) -> &N64Header {
+        &self.header
+    }
+
+    pub fn cic_info(&self) -> &CICInfo {
+        &self.cic_info
+    }
+
+    /// The header's libultra release, marked as the iQue build if the CIC is the iQue's
+    pub fn libultra(&self) -> LibultraVersion {
+        LibultraVersion::new(self.header.revision(), Some(self.cic_info.is_ique()))
+    }
+
+    /// Entrypoint after correcting for the CIC's offset
+    pub fn entrypoint(&self) -> u32 {
+        self.entrypoint
+    }
+
+    /// Replace the header, keeping the corrected entrypoint in step with it
+    pub fn set_header(&mut self, header: N64Header) {
+        self.data[..0x40].copy_from_slice(&header.to_bytes());
+        self.entrypoint = self.cic_info.correct_entrypoint(header.entrypoint());
+        self.header = header;
+    }
+
+    /// Compare the header's boot checksum against the one computed for this ROM's CIC
+    pub fn check_checksum(&self) -> Result<ChecksumCheck> {
+        Ok(ChecksumCheck {
+            stored: self.header.checksum(),
+            calculated: self.cic_info.calculate_checksum(&self.data)?,
+        })
+    }
+
+    /// Store the computed boot checksum in the header, returning the check from before the fix
+    pub fn fix_checksum(&mut self) -> Result<ChecksumCheck> {
+        let check = self.check_checksum()?;
+        let mut header = self.header.clone();
+        header.set_checksum(check.calculated);
+        self.set_header(header);
+        Ok(check)
+    }
+
+    /// A copy of the image cut or padded with `fill` to `new_size` bytes
+    pub fn resized(&self, new_size: usize, fill: u8) -> Result<Rom> {
+        let mut data = self.to_original_bytes();
+        data.resize(new_size, fill);
+        Rom::from_bytes(data)
+    }
+
+    /// The image without the padding to whole words, big-endian
+    pub fn unpadded_data(&self) -> &[u8] {
+        &self.data[..self.file_size as usize]
+    }
+
+    /// A ROM holding the big-endian image `data` that saves in this ROM's byte order
+    pub fn with_data(&self, mut data: Vec<u8>) -> Result<Rom> {
+        let len = data.len();
+        data.resize((len + 3) & !3, 0);
+        reend_array(&mut data, &self.endian_guess.endian);
+        data.truncate(len);
+        Rom::from_bytes(data)
+    }
+
+    /// The image in its original byte order
+    pub fn to_original_bytes(&self) -> Vec<u8> {
+        let mut data = self.data.clone();
+        reend_array(&mut data, &self.endian_guess.endian);
+        data.truncate(self.file_size as usize);
+        data
+    }
+
+    /// Write the image out in its original byte order
+    pub fn write(&self, mut writer: impl Write) -> Result<()> {
+        Ok(writer.write_all(&self.to_original_bytes())?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(fs::write(path, self.to_original_bytes())?)
+    }
+
+    /// `len` bytes starting at ROM `offset`
+    pub fn slice(&self, offset: u32, len: usize) -> Result<&[u8]> {
+        let start = offset as usize;
+        self.data
+            .get(start..start + len)
+            .ok_or(Error::TruncatedRom {
+                offset: offset.into(),
+                len,
+            })
+    }
+
+    /// Big-endian word at ROM `offset`
+    pub fn word(&self, offset: u32) -> Result<u32> {
+        Ok(u32::from_be_bytes(
+            self.slice(offset, 4)?.try_into().unwrap(),
+        ))
+    }
+
+    /// As much of the boot segment as the ROM contains
+    pub fn boot_segment(&self) -> &[u8] {
+        let start = (BOOT_SEGMENT_START as usize).min(self.data.len());
+        let end = (start + BOOT_SEGMENT_SIZE as usize).min(self.data.len());
+        &self.data[start..end]
+    }
+
+    /// Address the boot segment byte at ROM `offset` is loaded to, if it is in the boot segment
+    pub fn rom_to_vram(&self, offset: u32) -> Option<u32> {
+        (BOOT_SEGMENT_START..BOOT_SEGMENT_START + BOOT_SEGMENT_SIZE)
+            .contains(&offset)
+            .then(|| self.entrypoint.wrapping_add(offset - BOOT_SEGMENT_START))
+    }
+
+    /// ROM offset of the boot segment byte loaded to `vram`, if it is in the boot segment
+    pub fn vram_to_rom(&self, vram: u32) -> Option<u32> {
+        vram.checked_sub(self.entrypoint)
+            .filter(|offset| *offset < BOOT_SEGMENT_SIZE)
+            .map(|offset| offset + BOOT_SEGMENT_START)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::cart;
+
+    fn rom(country_code: u8, ipl3_byte: u8) -> Rom {
+        Rom::from_bytes(cart(country_code, ipl3_byte)).unwrap()
+    }
+
+    #[test]
+    fn libultra_ique_comes_from_cic() {
+        let ique = rom(b'C', 0);
+        assert_eq!(ique.header().libultra().is_ique(), None);
+        assert_eq!(ique.libultra().is_ique(), Some(true));
+        assert_eq!(ique.libultra().release_name().unwrap(), "2.0L (iQue)");
+
+        // A blank country code is not enough once the CIC has been identified
+        let homebrew = rom(0, 0x5A);
+        assert_eq!(homebrew.libultra().is_ique(), Some(false));
+        assert_eq!(homebrew.libultra().release_name().unwrap(), "2.0L");
+    }
+}
@@ -0,0 +1,116 @@
+//! How much of a ROM image is real data: trailing padding, overdumps and trimmed dumps
+
+use crate::n64header::ipl3::{CHECKSUM_LENGTH, CHECKSUM_START};
+
+/// Smallest cart size that covers everything the boot checksum reads
+pub const MIN_CART_SIZE: u64 = 0x200000;
+/// Largest cart the PI can address in domain 1
+pub const MAX_CART_SIZE: u64 = 0x4000000;
+
+/// Smallest cart size, a power of two, that holds `len` bytes
+pub fn cart_size_for(len: u64) -> u64 {
+    len.max(MIN_CART_SIZE).next_power_of_two()
+}
+
+/// Whether resizing between `from` and `to` bytes changes what the boot checksum reads
+pub fn touches_checksum(from: u64, to: u64) -> bool {
+    from.min(to) < (CHECKSUM_START + CHECKSUM_LENGTH) as u64
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct SizeInfo {
+    pub file_size: u64,
+    /// Size after removing repeated copies from an overdump, the same as `file_size` if none
+    pub real_size: u64,
+    /// End of the data before any trailing padding, within `real_size`
+    pub data_end: u64,
+    /// Byte the image is padded with after `data_end`, if any
+    pub padding: Option<u8>,
+    /// Smallest cart size the data fits in
+    pub cart_size: u64,
+}
+
+impl SizeInfo {
+    pub fn is_overdump(&self) -> bool {
+        self.real_size < self.file_size
+    }
+
+    /// Anything about the size worth flagging
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.is_overdump() {
+            warnings.push(format!(
+                "Overdump: image repeats every {:#X} bytes",
+                self.real_size
+            ));
+        }
+        if !self.file_size.is_power_of_two() {
+            warnings.push(format!(
+                "Size {:#X} is not a power of two, probably a trimmed dump",
+                self.file_size
+            ));
+        }
+        if self.file_size < (CHECKSUM_START + CHECKSUM_LENGTH) as u64 {
+            warnings.push(format!(
+                "Size {:#X} is too small for the boot checksum to be checked",
+                self.file_size
+            ));
+        }
+        if self.file_size > MAX_CART_SIZE {
+            warnings.push(format!(
+                "Size {:#X} is more than a cart can hold",
+                self.file_size
+            ));
+        }
+        warnings
+    }
+}
+
+impl std::fmt::Display for SizeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file size {:#X}", self.file_size)?;
+        if self.is_overdump() {
+            write!(f, ", real size {:#X}", self.real_size)?;
+        }
+        write!(f, ", data ends at {:#X}", self.data_end)?;
+        if let Some(padding) = self.padding {
+            write!(f, " (padded with {padding:02X})")?;
+        }
+        write!(f, ", fits a {} MiB cart", self.cart_size >> 20)
+    }
+}
+
+/// Look at the first `file_size` bytes of `data` for mirrored halves and trailing padding
+pub fn analyze(data: &[u8], file_size: u64) -> SizeInfo {
+    let data = &data[..file_size as usize];
+
+    // An overdump repeats the real image to fill the dumper's cart size
+    let mut real_size = data.len();
+    while real_size.is_power_of_two()
+        && real_size as u64 > MIN_CART_SIZE
+        && data[..real_size / 2] == data[real_size / 2..real_size]
+    {
+        real_size /= 2;
+    }
+
+    let real = &data[..real_size];
+    let padding = real
+        .last()
+        .copied()
+        .filter(|byte| matches!(byte, 0x00 | 0xFF));
+    let data_end = match padding {
+        Some(padding) => real
+            .iter()
+            .rposition(|&byte| byte != padding)
+            .map_or(0, |last| last + 1),
+        None => real_size,
+    };
+
+    SizeInfo {
+        file_size,
+        real_size: real_size as u64,
+        data_end: data_end as u64,
+        padding: padding.filter(|_| data_end < real_size),
+        cart_size: cart_size_for(data_end as u64),
+    }
+}
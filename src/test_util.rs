@@ -0,0 +1,50 @@
+//! Fixtures shared by the unit tests
+
+use crate::n64header::detect::DETECT_LEN;
+use crate::n64header::ipl3::{CHECKSUM_LENGTH, CHECKSUM_START};
+use crate::n64header::Endian;
+use crate::reend_array;
+
+/// Bytes that change from one seed to the next, without pulling in a random number crate
+pub fn noise(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+/// A big-endian ROM start with the given PI magic, a KSEG0 entrypoint, a media format, an IPL3
+/// of noise so misplaced bytes show, and a boot segment of ordinary instructions
+pub fn z64(magic: u32) -> Vec<u8> {
+    let mut rom = noise(DETECT_LEN, magic);
+    rom[0x00..0x04].copy_from_slice(&magic.to_be_bytes());
+    rom[0x08..0x0C].copy_from_slice(&0x80000400u32.to_be_bytes());
+    rom[0x38..0x3C].copy_from_slice(&u32::from(b'N').to_be_bytes());
+    let code = [
+        0x3C088004, 0x2508E940, 0xAD000000, 0x27BDFFE8, 0x0C000100, 0x03E00008,
+    ];
+    for (i, chunk) in rom[0x1000..].chunks_exact_mut(4).enumerate() {
+        chunk.copy_from_slice(&u32::to_be_bytes(code[i % code.len()]));
+    }
+    rom
+}
+
+/// `rom` as it would be stored in byte order `endian`
+pub fn stored(mut rom: Vec<u8>, endian: Endian) -> Vec<u8> {
+    reend_array(&mut rom, &endian);
+    rom
+}
+
+/// A big-endian 2.0L ROM long enough for a boot checksum, with the given country code and its
+/// IPL3 filled with `ipl3_byte`
+pub fn cart(country_code: u8, ipl3_byte: u8) -> Vec<u8> {
+    let mut rom = vec![0; CHECKSUM_START + CHECKSUM_LENGTH];
+    rom[0x00..0x04].copy_from_slice(&[0x80, 0x37, 0x12, 0x40]);
+    rom[0x0C..0x10].copy_from_slice(&[0, 0, 0x14, b'L']);
+    rom[0x3E] = country_code;
+    rom[0x40..0x1000].fill(ipl3_byte);
+    rom
+}